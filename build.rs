@@ -0,0 +1,36 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+// Embed every directory under migrations/ into the binary, with both its
+// up.sql and down.sql, so deploys do not need the diesel cli
+fn main() {
+    let root = Path::new(&env::var("CARGO_MANIFEST_DIR").unwrap()).join("migrations");
+    println!("cargo:rerun-if-changed={}", root.display());
+
+    let mut dirs: Vec<_> = fs::read_dir(&root)
+        .expect("Cannot read the migrations directory")
+        .map(|entry| entry.expect("Cannot read the migrations directory").path())
+        .filter(|path| path.is_dir())
+        .collect();
+    dirs.sort();
+
+    let mut out = String::from("pub const MIGRATIONS: &[EmbeddedMigration] = &[\n");
+    for dir in dirs {
+        println!("cargo:rerun-if-changed={}", dir.display());
+        let dir_name = dir.file_name().unwrap().to_string_lossy().to_string();
+        // same version as the diesel cli records, the timestamp without dashes
+        let (version, name) = dir_name.split_once('_').unwrap_or((&dir_name, ""));
+        out.push_str(&format!(
+            "    EmbeddedMigration {{\n        version: {:?},\n        name: {:?},\n        up_sql: include_str!({:?}),\n        down_sql: include_str!({:?}),\n    }},\n",
+            version.replace('-', ""),
+            name,
+            dir.join("up.sql").display().to_string(),
+            dir.join("down.sql").display().to_string(),
+        ));
+    }
+    out.push_str("];\n");
+
+    let dest = Path::new(&env::var("OUT_DIR").unwrap()).join("migrations.rs");
+    fs::write(dest, out).expect("Cannot write the embedded migrations");
+}
@@ -0,0 +1,23 @@
+-- Your SQL goes here
+
+-- Ledger of every change to a product's stock
+CREATE TABLE stock_movements
+(
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    kind varchar(20) NOT NULL CHECK (kind IN ('receipt', 'sale', 'adjustment', 'transfer', 'return')),
+    quantity FLOAT NOT NULL,
+    reason varchar(255) NOT NULL,
+    user_email varchar(100) NOT NULL,
+    user_company VARCHAR(100) NOT NULL,
+    created_at timestamp NOT NULL
+);
+
+-- Create Index on stock_movements table
+CREATE INDEX stock_movements_product_id_idx ON stock_movements (product_id, created_at);
+
+-- Seed the ledger with the current stock so it stays in sync with products.stock
+INSERT INTO stock_movements (product_id, kind, quantity, reason, user_email, user_company, created_at)
+SELECT id, 'adjustment', stock, 'Opening balance', 'system', 'system', NOW()
+FROM products
+WHERE stock <> 0;
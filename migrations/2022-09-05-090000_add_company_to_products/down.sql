@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX products_company_idx;
+ALTER TABLE products DROP COLUMN company;
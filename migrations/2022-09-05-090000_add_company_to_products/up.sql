@@ -0,0 +1,17 @@
+-- Your SQL goes here
+
+-- Products are owned by a company
+ALTER TABLE products ADD COLUMN company VARCHAR(100);
+
+-- Backfill existing products to the company of the first registered user,
+-- or a placeholder company when there are no users yet
+UPDATE products
+SET company = COALESCE(
+    (SELECT company FROM users ORDER BY created_at, id LIMIT 1),
+    'default'
+);
+
+ALTER TABLE products ALTER COLUMN company SET NOT NULL;
+
+-- Create Index on products table
+CREATE INDEX products_company_idx ON products (company);
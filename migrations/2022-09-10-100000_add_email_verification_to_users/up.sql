@@ -0,0 +1,27 @@
+-- Your SQL goes here
+
+-- Track when a user verified their email address
+ALTER TABLE users ADD COLUMN email_verified_at timestamp;
+
+-- Accounts created before verification existed are considered verified
+UPDATE users SET email_verified_at = created_at;
+
+-- An email address can only be registered once. Before this migration the
+-- same email could be registered under several companies; such accounts
+-- must be merged or renamed by hand first, as picking one to keep cannot be
+-- done safely here. Stop with a clear message instead of a bare unique
+-- violation.
+DO $$
+DECLARE
+    duplicated TEXT;
+BEGIN
+    SELECT string_agg(email, ', ' ORDER BY email) INTO duplicated
+    FROM (SELECT email FROM users GROUP BY email HAVING count(*) > 1) AS duplicates;
+    IF duplicated IS NOT NULL THEN
+        RAISE EXCEPTION 'Emails registered more than once, deduplicate them before migrating: %',
+            duplicated;
+    END IF;
+END
+$$;
+
+CREATE UNIQUE INDEX users_email_key ON users (email);
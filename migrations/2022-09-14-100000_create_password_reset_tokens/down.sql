@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE users DROP COLUMN sessions_invalidated_at;
+Drop table password_reset_tokens;
@@ -0,0 +1,18 @@
+-- Your SQL goes here
+
+-- Single-use password reset tokens, only the SHA-256 hash of the token is stored
+CREATE TABLE password_reset_tokens
+(
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    token_hash varchar(64) NOT NULL UNIQUE,
+    expires_at timestamp NOT NULL,
+    used_at timestamp,
+    created_at timestamp NOT NULL
+);
+
+-- Create Index on password_reset_tokens table
+CREATE INDEX password_reset_tokens_user_id_idx ON password_reset_tokens (user_id);
+
+-- Tokens issued before this moment are no longer accepted for the user
+ALTER TABLE users ADD COLUMN sessions_invalidated_at timestamp;
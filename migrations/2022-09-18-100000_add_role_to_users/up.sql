@@ -0,0 +1,8 @@
+-- Your SQL goes here
+
+-- Role of the user within their company
+ALTER TABLE users ADD COLUMN role varchar(20) NOT NULL DEFAULT 'viewer'
+    CHECK (role IN ('owner', 'admin', 'editor', 'viewer'));
+
+-- Existing users keep full access to their company
+UPDATE users SET role = 'owner';
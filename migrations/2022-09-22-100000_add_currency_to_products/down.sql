@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+COMMENT ON COLUMN products.price IS NULL;
+ALTER TABLE products DROP COLUMN currency;
@@ -0,0 +1,7 @@
+-- Your SQL goes here
+
+-- Prices are stored in the minor unit of an ISO 4217 currency
+ALTER TABLE products ADD COLUMN currency VARCHAR(3) NOT NULL DEFAULT 'USD'
+    CHECK (currency ~ '^[A-Z]{3}$');
+
+COMMENT ON COLUMN products.price IS 'Price in the minor unit of products.currency';
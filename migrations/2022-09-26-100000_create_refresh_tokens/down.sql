@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+Drop table revoked_tokens;
+Drop table refresh_tokens;
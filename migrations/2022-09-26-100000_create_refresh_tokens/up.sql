@@ -0,0 +1,29 @@
+-- Your SQL goes here
+
+-- Rotating refresh tokens, only the SHA-256 hash of the token is stored.
+-- Tokens rotated from the same login share a family.
+CREATE TABLE refresh_tokens
+(
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    family_id varchar(64) NOT NULL,
+    token_hash varchar(64) NOT NULL UNIQUE,
+    expires_at timestamp NOT NULL,
+    used_at timestamp,
+    revoked_at timestamp,
+    created_at timestamp NOT NULL
+);
+
+-- Create Index on refresh_tokens table
+CREATE INDEX refresh_tokens_family_id_idx ON refresh_tokens (family_id);
+CREATE INDEX refresh_tokens_user_id_idx ON refresh_tokens (user_id);
+
+-- Access tokens revoked before their expiry, identified by their jti claim
+CREATE TABLE revoked_tokens
+(
+    jti varchar(64) PRIMARY KEY,
+    expires_at timestamp NOT NULL
+);
+
+-- Create Index on revoked_tokens table
+CREATE INDEX revoked_tokens_expires_at_idx ON revoked_tokens (expires_at);
@@ -0,0 +1,16 @@
+-- Your SQL goes here
+
+-- Audit trail of every login attempt
+CREATE TABLE login_attempts
+(
+    id SERIAL PRIMARY KEY,
+    email varchar(100) NOT NULL,
+    ip_address varchar(45) NOT NULL,
+    succeeded BOOLEAN NOT NULL,
+    reason varchar(50),
+    created_at timestamp NOT NULL
+);
+
+-- Create Index on login_attempts table
+CREATE INDEX login_attempts_email_idx ON login_attempts (email, created_at);
+CREATE INDEX login_attempts_ip_address_idx ON login_attempts (ip_address, created_at);
@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+Drop table recovery_codes;
+ALTER TABLE users DROP COLUMN totp_last_step;
+ALTER TABLE users DROP COLUMN totp_enabled_at;
+ALTER TABLE users DROP COLUMN totp_secret;
@@ -0,0 +1,20 @@
+-- Your SQL goes here
+
+-- TOTP two-factor authentication. The secret is pending until enabled_at is set,
+-- last_step is the last accepted time step so a code cannot be replayed.
+ALTER TABLE users ADD COLUMN totp_secret varchar(64);
+ALTER TABLE users ADD COLUMN totp_enabled_at timestamp;
+ALTER TABLE users ADD COLUMN totp_last_step BIGINT;
+
+-- Single-use recovery codes, only the SHA-256 hash of a code is stored
+CREATE TABLE recovery_codes
+(
+    id SERIAL PRIMARY KEY,
+    user_id INTEGER NOT NULL REFERENCES users (id) ON DELETE CASCADE,
+    code_hash varchar(64) NOT NULL,
+    used_at timestamp,
+    created_at timestamp NOT NULL
+);
+
+-- Create Index on recovery_codes table
+CREATE INDEX recovery_codes_user_id_idx ON recovery_codes (user_id);
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+
+-- API keys for machine-to-machine access. A key is looked up by its public
+-- prefix, only the SHA-256 hash of the whole key is stored.
+CREATE TABLE api_keys
+(
+    id SERIAL PRIMARY KEY,
+    company varchar(100) NOT NULL,
+    name varchar(100) NOT NULL,
+    prefix varchar(16) NOT NULL UNIQUE,
+    key_hash varchar(64) NOT NULL,
+    scopes TEXT[] NOT NULL,
+    created_by varchar(100) NOT NULL,
+    expires_at timestamp,
+    last_used_at timestamp,
+    revoked_at timestamp,
+    created_at timestamp NOT NULL
+);
+
+-- Create Index on api_keys table
+CREATE INDEX api_keys_company_idx ON api_keys (company);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+
+-- Disabled users can no longer sign in, set through the admin cli
+ALTER TABLE users ADD COLUMN disabled_at TIMESTAMP;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+Drop table product_categories;
+Drop table categories;
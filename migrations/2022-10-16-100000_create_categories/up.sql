@@ -0,0 +1,28 @@
+-- Your SQL goes here
+
+-- Nested product categories. `path` holds the ids from the root down to the
+-- category itself, e.g. '/1/4/9/', so a subtree is every path with its prefix.
+CREATE TABLE categories
+(
+    id SERIAL PRIMARY KEY,
+    company varchar(100) NOT NULL,
+    parent_id INTEGER REFERENCES categories (id),
+    name varchar(100) NOT NULL,
+    path TEXT NOT NULL,
+    created_at timestamp NOT NULL
+);
+
+-- Names are unique among siblings
+CREATE UNIQUE INDEX categories_sibling_name_idx
+    ON categories (company, COALESCE(parent_id, 0), lower(name));
+CREATE INDEX categories_path_idx ON categories (company, path text_pattern_ops);
+
+-- Categories a product belongs to
+CREATE TABLE product_categories
+(
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    category_id INTEGER NOT NULL REFERENCES categories (id) ON DELETE CASCADE,
+    PRIMARY KEY (product_id, category_id)
+);
+
+CREATE INDEX product_categories_category_idx ON product_categories (category_id);
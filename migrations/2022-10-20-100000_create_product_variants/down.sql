@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE stock_movements DROP COLUMN variant_id;
+Drop table product_variants;
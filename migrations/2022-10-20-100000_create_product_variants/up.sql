@@ -0,0 +1,26 @@
+-- Your SQL goes here
+
+-- Sellable variants of a product, e.g. a size and a colour. The stock of a
+-- product with variants is held by them, products.stock is their sum.
+CREATE TABLE product_variants
+(
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    company varchar(100) NOT NULL,
+    sku varchar(64) NOT NULL,
+    options JSONB NOT NULL DEFAULT '{}',
+    price INTEGER,
+    currency VARCHAR(3) CHECK (currency ~ '^[A-Z]{3}$'),
+    stock DOUBLE PRECISION NOT NULL DEFAULT 0 CHECK (stock >= 0),
+    created_at timestamp NOT NULL,
+    CHECK ((price IS NULL) = (currency IS NULL))
+);
+
+COMMENT ON COLUMN product_variants.price IS 'Overrides products.price when set';
+
+CREATE UNIQUE INDEX product_variants_sku_idx ON product_variants (company, sku);
+-- no two variants of a product share the same option values
+CREATE UNIQUE INDEX product_variants_options_idx ON product_variants (product_id, options);
+
+ALTER TABLE stock_movements
+    ADD COLUMN variant_id INTEGER REFERENCES product_variants (id) ON DELETE SET NULL;
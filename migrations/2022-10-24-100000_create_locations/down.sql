@@ -0,0 +1,4 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE stock_movements DROP COLUMN location_id;
+Drop table stock_levels;
+Drop table locations;
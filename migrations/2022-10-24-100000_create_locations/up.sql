@@ -0,0 +1,30 @@
+-- Your SQL goes here
+
+-- Warehouses and other places stock is held at
+CREATE TABLE locations
+(
+    id SERIAL PRIMARY KEY,
+    company varchar(100) NOT NULL,
+    name varchar(100) NOT NULL,
+    created_at timestamp NOT NULL
+);
+
+CREATE UNIQUE INDEX locations_name_idx ON locations (company, lower(name));
+
+-- Stock of a product, or of one of its variants, held at a location. What
+-- products.stock holds beyond its levels is not at any location yet.
+CREATE TABLE stock_levels
+(
+    id SERIAL PRIMARY KEY,
+    product_id INTEGER NOT NULL REFERENCES products (id) ON DELETE CASCADE,
+    variant_id INTEGER REFERENCES product_variants (id) ON DELETE CASCADE,
+    location_id INTEGER NOT NULL REFERENCES locations (id) ON DELETE CASCADE,
+    quantity DOUBLE PRECISION NOT NULL DEFAULT 0 CHECK (quantity >= 0)
+);
+
+CREATE UNIQUE INDEX stock_levels_target_idx
+    ON stock_levels (product_id, COALESCE(variant_id, 0), location_id);
+CREATE INDEX stock_levels_location_idx ON stock_levels (location_id);
+
+ALTER TABLE stock_movements
+    ADD COLUMN location_id INTEGER REFERENCES locations (id) ON DELETE SET NULL;
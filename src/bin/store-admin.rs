@@ -0,0 +1,373 @@
+// Administration from the command line for what has no endpoint, such as the
+// first user of a company. Results are printed as JSON on stdout, errors as
+// JSON on stderr with a non-zero exit code.
+use std::fs::File;
+use std::io::{self, BufRead, Read, Write};
+use std::process;
+
+use chrono::{Local, NaiveDateTime};
+use clap::{Parser, Subcommand};
+use diesel::pg::PgConnection;
+use serde::Serialize;
+use serde_json::json;
+use validator::{Validate, ValidationErrors};
+
+use rust_store_v2::config::Settings;
+use rust_store_v2::db_connection;
+use rust_store_v2::errors::application_error::ApplicationError;
+use rust_store_v2::errors::server_error::ServerError;
+use rust_store_v2::models::product_csv::{
+    self, export_batch, write_rows, ImportMode, EXPORT_BATCH_SIZE,
+};
+use rust_store_v2::models::role::Role;
+use rust_store_v2::models::user::{RegisterUser, User};
+use rust_store_v2::utils::jwt::SlimUser;
+use rust_store_v2::utils::validation::validate_password_strength;
+
+// Catalog loaded by `db seed`
+const SEED_PRODUCTS: &str = include_str!("../../seeds/products.csv");
+
+#[derive(Parser)]
+#[command(name = "store-admin", about = "Administration of the store")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    #[command(subcommand, about = "Manage users")]
+    User(UserCommand),
+    #[command(subcommand, about = "Import and export the catalog of a company")]
+    Product(ProductCommand),
+    #[command(subcommand, about = "Database maintenance")]
+    Db(DbCommand),
+}
+
+#[derive(Subcommand)]
+enum UserCommand {
+    #[command(about = "Create a user with a verified email")]
+    Create {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        company: String,
+        #[arg(long, help = "Defaults to owner for a new company, viewer otherwise")]
+        role: Option<Role>,
+        #[arg(long, help = "Read from the first line of stdin when not given")]
+        password: Option<String>,
+    },
+    #[command(about = "Set the password of a user and end their sessions")]
+    SetPassword {
+        #[arg(long)]
+        email: String,
+        #[arg(long, help = "Read from the first line of stdin when not given")]
+        password: Option<String>,
+    },
+    #[command(about = "Change the role of a user and end their sessions")]
+    SetRole {
+        #[arg(long)]
+        email: String,
+        #[arg(long)]
+        role: Role,
+    },
+    #[command(about = "Stop a user from signing in and end their sessions")]
+    Disable {
+        #[arg(long)]
+        email: String,
+    },
+    #[command(about = "Allow a disabled user to sign in again")]
+    Enable {
+        #[arg(long)]
+        email: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProductCommand {
+    #[command(about = "Import a catalog CSV as the given user")]
+    Import {
+        #[arg(long, help = "User the products are created by, sets the company")]
+        user: String,
+        #[arg(long)]
+        dry_run: bool,
+        #[arg(help = "CSV file, stdin when not given or -")]
+        file: Option<String>,
+    },
+    #[command(about = "Export the catalog of a company as CSV")]
+    Export {
+        #[arg(long)]
+        company: String,
+        #[arg(long, help = "File to write, stdout when not given")]
+        output: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum DbCommand {
+    #[command(
+        about = "Create an owner and a sample catalog, running it again only adds missing products"
+    )]
+    Seed {
+        #[arg(long, default_value = "demo")]
+        company: String,
+        #[arg(long, default_value = "owner@demo.example")]
+        email: String,
+        #[arg(
+            long,
+            help = "Password of the owner when it is created, read from stdin when not given"
+        )]
+        password: Option<String>,
+    },
+}
+
+// What is printed for a user, including the fields hidden from the API
+#[derive(Serialize)]
+struct UserOutput {
+    email: String,
+    company: String,
+    role: String,
+    created_at: NaiveDateTime,
+    email_verified_at: Option<NaiveDateTime>,
+    disabled_at: Option<NaiveDateTime>,
+    two_factor_enabled: bool,
+}
+
+impl From<User> for UserOutput {
+    fn from(user: User) -> Self {
+        UserOutput {
+            two_factor_enabled: user.two_factor_enabled(),
+            email: user.email,
+            company: user.company,
+            role: user.role,
+            created_at: user.created_at,
+            email_verified_at: user.email_verified_at,
+            disabled_at: user.disabled_at,
+        }
+    }
+}
+
+fn internal(err: impl ToString) -> ServerError {
+    ServerError::InternalServerError(err.to_string())
+}
+
+// Name the missing user instead of the generic not found message
+fn no_user(email: &str) -> impl Fn(ApplicationError) -> ServerError + '_ {
+    move |err| match err {
+        ApplicationError::DBError(diesel::result::Error::NotFound) => {
+            ServerError::NotFound(format!("No user with email {}", email))
+        }
+        _ => err.into(),
+    }
+}
+
+fn print_json(value: &impl Serialize) -> Result<(), ServerError> {
+    let json = serde_json::to_string_pretty(value).map_err(internal)?;
+    println!("{}", json);
+    Ok(())
+}
+
+fn read_password(password: Option<String>) -> Result<String, ServerError> {
+    match password {
+        Some(password) => Ok(password),
+        None => {
+            let mut line = String::new();
+            io::stdin().lock().read_line(&mut line).map_err(internal)?;
+            Ok(line.trim_end_matches(['\r', '\n']).to_string())
+        }
+    }
+}
+
+fn check_password(password: &str) -> Result<(), ServerError> {
+    validate_password_strength(password).map_err(|err| {
+        let mut errors = ValidationErrors::new();
+        errors.add("password", err);
+        ServerError::from(errors)
+    })
+}
+
+// Act as the given user, the way their session would
+fn actor(user: &User) -> SlimUser {
+    let now = Local::now().timestamp() as usize;
+    SlimUser {
+        email: user.email.clone(),
+        company: user.company.clone(),
+        issued_at: now,
+        expires_at: now,
+        token_id: "store-admin".to_string(),
+        role: user.role(),
+        api_key_id: None,
+        api_scopes: Vec::new(),
+    }
+}
+
+fn create_user(
+    email: String,
+    company: String,
+    role: Option<Role>,
+    password: String,
+    conn: &PgConnection,
+) -> Result<User, ServerError> {
+    let register_user = RegisterUser {
+        email,
+        company,
+        password_confirmation: password.clone(),
+        password,
+    };
+    register_user.validate()?;
+    let now = Local::now().naive_local();
+    Ok(User::create_with_role(
+        &register_user,
+        role,
+        Some(now),
+        conn,
+    )?)
+}
+
+fn user_command(command: UserCommand, conn: &PgConnection) -> Result<(), ServerError> {
+    let user = match command {
+        UserCommand::Create {
+            email,
+            company,
+            role,
+            password,
+        } => create_user(email, company, role, read_password(password)?, conn)?,
+        UserCommand::SetPassword { email, password } => {
+            let password = read_password(password)?;
+            check_password(&password)?;
+            User::set_password(&email, &password, conn).map_err(no_user(&email))?
+        }
+        UserCommand::SetRole { email, role } => {
+            User::set_role(&email, role, conn).map_err(no_user(&email))?
+        }
+        UserCommand::Disable { email } => User::disable(&email, conn).map_err(no_user(&email))?,
+        UserCommand::Enable { email } => User::enable(&email, conn).map_err(no_user(&email))?,
+    };
+    print_json(&UserOutput::from(user))
+}
+
+fn product_command(command: ProductCommand, conn: &PgConnection) -> Result<(), ServerError> {
+    match command {
+        ProductCommand::Import {
+            user,
+            dry_run,
+            file,
+        } => {
+            let user = User::find_by_email(&user, conn).map_err(no_user(&user))?;
+            let input: Box<dyn Read> = match file.as_deref() {
+                None | Some("-") => Box::new(io::stdin()),
+                Some(path) => Box::new(File::open(path).map_err(internal)?),
+            };
+            let report =
+                product_csv::import(input, ImportMode::Upsert, dry_run, &actor(&user), conn)?;
+            print_json(&report)?;
+            if !report.errors.is_empty() {
+                return Err(ServerError::UnprocessableEntity(format!(
+                    "{} invalid rows, nothing was imported",
+                    report.errors.len()
+                )));
+            }
+            Ok(())
+        }
+        ProductCommand::Export { company, output } => {
+            let mut writer: Box<dyn Write> = match output.as_deref() {
+                None | Some("-") => Box::new(io::stdout()),
+                Some(path) => Box::new(File::create(path).map_err(internal)?),
+            };
+            let mut rows = 0;
+            let mut after_id = 0;
+            loop {
+                let batch = export_batch(&company, after_id, conn)?;
+                writer
+                    .write_all(&write_rows(&batch, after_id == 0)?)
+                    .map_err(internal)?;
+                rows += batch.len();
+                match batch.last() {
+                    Some(last) if batch.len() as i64 == EXPORT_BATCH_SIZE => after_id = last.id,
+                    _ => break,
+                }
+            }
+            writer.flush().map_err(internal)?;
+            // the CSV itself is the output when written to stdout
+            match output {
+                Some(path) if path != "-" => print_json(&json!({
+                    "company": company,
+                    "rows": rows,
+                    "output": path,
+                })),
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+fn db_command(command: DbCommand, conn: &PgConnection) -> Result<(), ServerError> {
+    match command {
+        DbCommand::Seed {
+            company,
+            email,
+            password,
+        } => {
+            let (user, user_created) = match User::find_by_email(&email, conn) {
+                Ok(user) => (user, false),
+                Err(ApplicationError::DBError(diesel::result::Error::NotFound)) => {
+                    let password = read_password(password)?;
+                    let user =
+                        create_user(email, company.clone(), Some(Role::Owner), password, conn)?;
+                    (user, true)
+                }
+                Err(err) => return Err(err.into()),
+            };
+            if user.company != company {
+                return Err(ServerError::Conflict(format!(
+                    "{} belongs to {}, not {}",
+                    user.email, user.company, company
+                )));
+            }
+            // products are matched by name, seeding again only adds the
+            // missing ones and leaves real stock and prices alone
+            let report = product_csv::import(
+                SEED_PRODUCTS.as_bytes(),
+                ImportMode::InsertOnly,
+                false,
+                &actor(&user),
+                conn,
+            )?;
+            print_json(&json!({
+                "user": UserOutput::from(user),
+                "user_created": user_created,
+                "products": report,
+            }))
+        }
+    }
+}
+
+fn fail(err: ServerError) -> ! {
+    let detail = match &err {
+        // the operator may see what went wrong
+        ServerError::InternalServerError(msg) => msg.clone(),
+        _ => err.detail(),
+    };
+    let mut error = json!({ "code": err.code(), "detail": detail });
+    if let ServerError::ValidationFailed(errors) = &err {
+        error["errors"] = json!(errors);
+    }
+    let body = json!({ "error": error });
+    eprintln!("{}", body);
+    process::exit(1)
+}
+
+fn main() {
+    let cli = Cli::parse();
+    let settings = Settings::load_database().unwrap_or_else(|err| fail(internal(err)));
+    let conn = db_connection::connect(&settings)
+        .unwrap_or_else(|err| fail(internal(format!("Cannot connect to the database: {}", err))));
+    let result = match cli.command {
+        Command::User(command) => user_command(command, &conn),
+        Command::Product(command) => product_command(command, &conn),
+        Command::Db(command) => db_command(command, &conn),
+    };
+    if let Err(err) = result {
+        fail(err);
+    }
+}
@@ -0,0 +1,309 @@
+use std::env;
+use std::net::IpAddr;
+use std::path::Path;
+
+use ::config::{Config, ConfigError, Environment, File};
+use data_encoding::BASE64;
+use dotenv::dotenv;
+use serde::Deserialize;
+
+// Settings are read in this order, later sources override earlier ones:
+// - config/default.toml
+// - config/<APP_ENV>.toml, APP_ENV defaults to `development`
+// - config/local.toml, not checked in
+// - APP_<SECTION>__<KEY> environment variables, e.g. APP_SERVER__PORT=8080
+// - DATABASE_URL, shared with the diesel cli, always wins for database.url
+pub const CONFIG_DIR: &str = "config";
+pub const ENV_PREFIX: &str = "APP";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Settings {
+    pub server: ServerSettings,
+    pub database: DatabaseSettings,
+    pub redis: RedisSettings,
+    pub cors: CorsSettings,
+    pub session: SessionSettings,
+    pub jwt: JwtSettings,
+    pub csrf: CsrfSettings,
+    pub mail: MailSettings,
+    pub metrics: MetricsSettings,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct ServerSettings {
+    pub host: String,
+    pub port: u16,
+    // defaults to the number of cpus when unset
+    pub workers: Option<usize>,
+    // blocking threads per worker for database queries and password hashing,
+    // actix picks a default from the number of cpus when unset
+    pub max_blocking_threads: Option<usize>,
+    // public url of the app, used in links sent by email
+    pub app_url: String,
+    // default log filter, RUST_LOG takes precedence
+    pub log_level: String,
+    // reverse proxies whose X-Forwarded-For header names the client
+    pub trusted_proxies: Vec<IpAddr>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct DatabaseSettings {
+    pub url: String,
+    pub max_connections: u32,
+    pub min_idle: Option<u32>,
+    pub connection_timeout_seconds: u64,
+    // apply pending migrations at startup, replicas take turns through an
+    // advisory lock
+    pub run_migrations: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct RedisSettings {
+    pub url: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CorsSettings {
+    pub allowed_origins: Vec<String>,
+    pub max_age_seconds: usize,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionSettings {
+    // at least 64 bytes encoded as base64, signs and encrypts the session cookie
+    pub key: String,
+    pub cookie_secure: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct JwtSettings {
+    pub signing_kid: String,
+    pub signing_key_file: String,
+    // optional directory of `<kid>.pub.pem` files for retired keys
+    pub verification_keys_dir: Option<String>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct CsrfSettings {
+    // 32 bytes encoded as base64
+    pub key: String,
+    pub token_ttl_seconds: i64,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MailSettings {
+    pub outbox_dir: String,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct MetricsSettings {
+    // bearer token scrapers must send, /metrics answers 404 while it is empty
+    pub token: String,
+}
+
+impl DatabaseSettings {
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.url.is_empty() {
+            errors.push("database.url must be set".to_string());
+        }
+        if self.max_connections == 0 {
+            errors.push("database.max_connections must be at least 1".to_string());
+        }
+        if self
+            .min_idle
+            .is_some_and(|min_idle| min_idle > self.max_connections)
+        {
+            errors.push("database.min_idle must not exceed database.max_connections".to_string());
+        }
+        if self.connection_timeout_seconds == 0 {
+            errors.push("database.connection_timeout_seconds must be at least 1".to_string());
+        }
+        errors
+    }
+}
+
+fn check(errors: Vec<String>) -> Result<(), String> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(format!("Invalid configuration:\n  {}", errors.join("\n  ")))
+    }
+}
+
+// Decode a base64 key and check its length
+pub fn decode_key(value: &str, min_len: usize) -> Result<Vec<u8>, String> {
+    if value.trim().is_empty() {
+        return Err("must be set".to_string());
+    }
+    let key = BASE64
+        .decode(value.trim().as_bytes())
+        .map_err(|_| "must be encoded as base64".to_string())?;
+    if key.len() < min_len {
+        return Err(format!("must be at least {} bytes", min_len));
+    }
+    Ok(key)
+}
+
+impl Settings {
+    // Load and validate the settings, the error lists every problem found
+    pub fn load() -> Result<Settings, String> {
+        let settings = Self::load_unchecked()?;
+        check(settings.validate())?;
+        Ok(settings)
+    }
+
+    // Load only what the admin cli needs, so it runs without the server's
+    // secrets being configured
+    pub fn load_database() -> Result<DatabaseSettings, String> {
+        let settings = Self::load_unchecked()?;
+        check(settings.database.validate())?;
+        Ok(settings.database)
+    }
+
+    // Defaults and config files without validation, for handler tests
+    #[cfg(test)]
+    pub fn for_tests() -> Settings {
+        Self::read("test").expect("default settings")
+    }
+
+    fn load_unchecked() -> Result<Settings, String> {
+        dotenv().ok();
+        let app_env = env::var("APP_ENV").unwrap_or_else(|_| "development".to_string());
+        Self::read(&app_env).map_err(|err| format!("Invalid configuration: {}", err))
+    }
+
+    fn read(app_env: &str) -> Result<Settings, ConfigError> {
+        let dir = Path::new(CONFIG_DIR);
+        Config::builder()
+            .set_default("server.host", "127.0.0.1")?
+            .set_default("server.port", 8088)?
+            .set_default("server.app_url", "http://127.0.0.1:8088")?
+            .set_default("server.log_level", "info")?
+            .set_default("server.trusted_proxies", Vec::<String>::new())?
+            .set_default("database.url", "")?
+            .set_default("database.max_connections", 10)?
+            .set_default("database.connection_timeout_seconds", 30)?
+            .set_default("database.run_migrations", false)?
+            .set_default("redis.url", "redis://127.0.0.1:6379")?
+            .set_default("cors.allowed_origins", Vec::<String>::new())?
+            .set_default("cors.max_age_seconds", 3600)?
+            // secrets have no usable default, validation reports them as missing
+            .set_default("session.key", "")?
+            .set_default("session.cookie_secure", true)?
+            .set_default("jwt.signing_kid", "")?
+            .set_default("jwt.signing_key_file", "")?
+            .set_default("csrf.key", "")?
+            .set_default("csrf.token_ttl_seconds", 3600)?
+            .set_default("mail.outbox_dir", "outbox")?
+            .set_default("metrics.token", "")?
+            .add_source(File::from(dir.join("default")).required(false))
+            .add_source(File::from(dir.join(app_env)).required(false))
+            .add_source(File::from(dir.join("local")).required(false))
+            .add_source(
+                Environment::with_prefix(ENV_PREFIX)
+                    .prefix_separator("_")
+                    .separator("__")
+                    .list_separator(",")
+                    .with_list_parse_key("cors.allowed_origins")
+                    .with_list_parse_key("server.trusted_proxies")
+                    .try_parsing(true),
+            )
+            .set_override_option("database.url", env::var("DATABASE_URL").ok())?
+            .build()?
+            .try_deserialize()
+    }
+
+    fn validate(&self) -> Vec<String> {
+        let mut errors = Vec::new();
+        if self.server.port == 0 {
+            errors.push("server.port must not be 0".to_string());
+        }
+        if self.server.workers == Some(0) {
+            errors.push("server.workers must be at least 1".to_string());
+        }
+        if self.server.max_blocking_threads == Some(0) {
+            errors.push("server.max_blocking_threads must be at least 1".to_string());
+        }
+        if !self.server.app_url.starts_with("http://")
+            && !self.server.app_url.starts_with("https://")
+        {
+            errors.push("server.app_url must be an http or https url".to_string());
+        }
+        errors.extend(self.database.validate());
+        if !self.redis.url.starts_with("redis://") && !self.redis.url.starts_with("rediss://") {
+            errors.push("redis.url must be a redis:// or rediss:// url".to_string());
+        }
+        for origin in &self.cors.allowed_origins {
+            let host = origin
+                .strip_prefix("https://")
+                .or_else(|| origin.strip_prefix("http://"));
+            if host.is_none_or(|host| host.is_empty() || host.contains('/')) {
+                errors.push(format!(
+                    "cors.allowed_origins: {} must be a scheme and host such as https://shop.example.com",
+                    origin
+                ));
+            }
+        }
+        if let Err(err) = decode_key(&self.session.key, 64) {
+            errors.push(format!("session.key {}", err));
+        }
+        if self.jwt.signing_kid.is_empty() {
+            errors.push("jwt.signing_kid must be set".to_string());
+        }
+        if self.jwt.signing_key_file.is_empty() {
+            errors.push("jwt.signing_key_file must be set".to_string());
+        } else if !Path::new(&self.jwt.signing_key_file).is_file() {
+            errors.push(format!(
+                "jwt.signing_key_file {} does not exist",
+                self.jwt.signing_key_file
+            ));
+        }
+        if let Some(dir) = &self.jwt.verification_keys_dir {
+            if !Path::new(dir).is_dir() {
+                errors.push(format!("jwt.verification_keys_dir {} does not exist", dir));
+            }
+        }
+        match decode_key(&self.csrf.key, 32) {
+            Ok(key) if key.len() != 32 => {
+                errors.push("csrf.key must be exactly 32 bytes".to_string())
+            }
+            Ok(_) => {}
+            Err(err) => errors.push(format!("csrf.key {}", err)),
+        }
+        if self.csrf.token_ttl_seconds <= 0 {
+            errors.push("csrf.token_ttl_seconds must be positive".to_string());
+        }
+        if !self.metrics.token.is_empty() && self.metrics.token.len() < 32 {
+            errors.push("metrics.token must be at least 32 characters".to_string());
+        }
+        errors
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_a_base64_key() {
+        let encoded = BASE64.encode(&[7u8; 32]);
+        assert_eq!(
+            decode_key(&format!(" {}\n", encoded), 32),
+            Ok(vec![7u8; 32])
+        );
+    }
+
+    #[test]
+    fn rejects_missing_malformed_and_short_keys() {
+        assert_eq!(decode_key("  ", 32), Err("must be set".to_string()));
+        assert_eq!(
+            decode_key("not base64!", 32),
+            Err("must be encoded as base64".to_string())
+        );
+        assert_eq!(
+            decode_key(&BASE64.encode(&[7u8; 16]), 32),
+            Err("must be at least 32 bytes".to_string())
+        );
+    }
+}
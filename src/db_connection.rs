@@ -1,27 +1,33 @@
 use diesel::pg::PgConnection;
 use diesel::r2d2::{ConnectionManager, Pool, PoolError, PooledConnection};
+use diesel::{Connection, ConnectionResult};
+use std::time::Duration;
 
-use dotenv::dotenv;
-use std::env;
+use crate::config::DatabaseSettings;
+use crate::utils::metrics::PoolMetrics;
 
 // Adding connection pool Type
 pub type PgPool = Pool<ConnectionManager<PgConnection>>;
 pub type PgPooledConnection = PooledConnection<ConnectionManager<PgConnection>>;
 
 // Init connection pool
-pub fn init_pool(database_url: &str) -> Result<PgPool, PoolError> {
-    let manager = ConnectionManager::<PgConnection>::new(database_url.to_string());
-    Pool::builder().build(manager)
+pub fn init_pool(settings: &DatabaseSettings) -> Result<PgPool, PoolError> {
+    let manager = ConnectionManager::<PgConnection>::new(settings.url.to_string());
+    Pool::builder()
+        .max_size(settings.max_connections)
+        .min_idle(settings.min_idle)
+        .connection_timeout(Duration::from_secs(settings.connection_timeout_seconds))
+        .event_handler(Box::new(PoolMetrics))
+        .build(manager)
 }
 
 // Establish a connection to the database.
-pub fn establish_connection() -> PgPool {
-    // Load the environment variables from the .env file.
-    dotenv().ok();
-
-    // Get the database URL from the environment variables.
-    let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
-
+pub fn establish_connection(settings: &DatabaseSettings) -> PgPool {
     // Create a connection to the database.
-    init_pool(&database_url).expect("Error connecting to database")
+    init_pool(settings).expect("Error connecting to database")
+}
+
+// Open a single connection, outside of the pool, for migrations and the admin cli
+pub fn connect(settings: &DatabaseSettings) -> ConnectionResult<PgConnection> {
+    PgConnection::establish(&settings.url)
 }
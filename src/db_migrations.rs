@@ -0,0 +1,176 @@
+use std::io;
+
+use diesel::connection::SimpleConnection;
+use diesel::pg::PgConnection;
+use diesel::sql_types::{BigInt, Text};
+use diesel::{Connection, RunQueryDsl};
+use diesel_migrations::{
+    run_migrations, setup_database, Migration, MigrationConnection, RunMigrationsError,
+};
+
+use crate::config::DatabaseSettings;
+use crate::db_connection;
+
+// Migrations from the migrations/ directory, generated by build.rs
+pub struct EmbeddedMigration {
+    pub version: &'static str,
+    pub name: &'static str,
+    pub up_sql: &'static str,
+    pub down_sql: &'static str,
+}
+
+include!(concat!(env!("OUT_DIR"), "/migrations.rs"));
+
+// Key of the postgres advisory lock held while migrating, so replicas
+// starting together do not run the same migration twice
+const MIGRATION_LOCK_ID: i64 = 0x7275_7374_5f73_746f;
+
+impl Migration for EmbeddedMigration {
+    fn version(&self) -> &str {
+        self.version
+    }
+
+    fn run(&self, conn: &dyn SimpleConnection) -> Result<(), RunMigrationsError> {
+        conn.batch_execute(self.up_sql).map_err(Into::into)
+    }
+
+    fn revert(&self, conn: &dyn SimpleConnection) -> Result<(), RunMigrationsError> {
+        conn.batch_execute(self.down_sql).map_err(Into::into)
+    }
+}
+
+impl EmbeddedMigration {
+    // `<version>_<name>`, the version without the dashes of the directory name
+    pub fn full_name(&self) -> String {
+        format!("{}_{}", self.version, self.name)
+    }
+}
+
+// A migration and whether it ran on the database
+pub struct MigrationStatus {
+    pub migration: &'static EmbeddedMigration,
+    pub applied: bool,
+}
+
+// Every embedded migration, oldest first
+pub fn status(conn: &PgConnection) -> Result<Vec<MigrationStatus>, RunMigrationsError> {
+    setup_database(conn)?;
+    let applied = conn.previously_run_migration_versions()?;
+    Ok(MIGRATIONS
+        .iter()
+        .map(|migration| MigrationStatus {
+            migration,
+            applied: applied.contains(migration.version),
+        })
+        .collect())
+}
+
+// Hold the migration lock while `f` runs, waiting for other holders first
+fn with_lock<T, F>(conn: &PgConnection, f: F) -> Result<T, RunMigrationsError>
+where
+    F: FnOnce() -> Result<T, RunMigrationsError>,
+{
+    diesel::sql_query("SELECT pg_advisory_lock($1)")
+        .bind::<BigInt, _>(MIGRATION_LOCK_ID)
+        .execute(conn)?;
+    let result = f();
+    diesel::sql_query("SELECT pg_advisory_unlock($1)")
+        .bind::<BigInt, _>(MIGRATION_LOCK_ID)
+        .execute(conn)?;
+    result
+}
+
+// Apply the pending migrations, each in its own transaction, and return them
+pub fn run_pending(
+    conn: &PgConnection,
+) -> Result<Vec<&'static EmbeddedMigration>, RunMigrationsError> {
+    with_lock(conn, || {
+        // read under the lock, another replica may just have migrated
+        let pending: Vec<_> = status(conn)?
+            .into_iter()
+            .filter(|status| !status.applied)
+            .map(|status| status.migration)
+            .collect();
+        run_migrations(
+            conn,
+            pending.iter().map(|migration| *migration as &dyn Migration),
+            &mut io::sink(),
+        )?;
+        Ok(pending)
+    })
+}
+
+// Revert the most recently applied migration, `None` when nothing was applied
+pub fn revert_latest(
+    conn: &PgConnection,
+) -> Result<Option<&'static EmbeddedMigration>, RunMigrationsError> {
+    with_lock(conn, || {
+        setup_database(conn)?;
+        let latest = match conn.latest_run_migration_version()? {
+            Some(version) => version,
+            None => return Ok(None),
+        };
+        let migration = MIGRATIONS
+            .iter()
+            .find(|migration| migration.version == latest)
+            .ok_or_else(|| {
+                RunMigrationsError::MigrationError(
+                    diesel_migrations::MigrationError::UnknownMigrationVersion(latest.clone()),
+                )
+            })?;
+        conn.transaction(|| {
+            migration.revert(conn)?;
+            diesel::sql_query("DELETE FROM __diesel_schema_migrations WHERE version = $1")
+                .bind::<Text, _>(migration.version)
+                .execute(conn)?;
+            Ok(Some(migration))
+        })
+    })
+}
+
+fn connect(settings: &DatabaseSettings) -> Result<PgConnection, String> {
+    db_connection::connect(settings)
+        .map_err(|err| format!("Cannot connect to the database: {}", err))
+}
+
+// Apply pending migrations before serving, when `database.run_migrations` is set
+pub fn migrate_on_startup(settings: &DatabaseSettings) -> Result<(), String> {
+    let conn = connect(settings)?;
+    let applied = run_pending(&conn).map_err(|err| format!("Migration failed: {}", err))?;
+    for migration in &applied {
+        log::info!("Applied migration {}", migration.full_name());
+    }
+    Ok(())
+}
+
+pub const MIGRATE_USAGE: &str = "Usage: rust-store-v2 migrate [list|run|revert]";
+
+// `migrate list` shows every migration, `migrate run` applies the pending ones
+// and `migrate revert` undoes the latest one
+pub fn migrate_command(settings: &DatabaseSettings, command: Option<&str>) -> Result<(), String> {
+    let conn = connect(settings)?;
+    let failed = |err: RunMigrationsError| format!("Migration failed: {}", err);
+    match command.unwrap_or("list") {
+        "list" => {
+            for status in status(&conn).map_err(failed)? {
+                let mark = if status.applied { "X" } else { " " };
+                println!("[{}] {}", mark, status.migration.full_name());
+            }
+        }
+        "run" => {
+            let applied = run_pending(&conn).map_err(failed)?;
+            if applied.is_empty() {
+                println!("No pending migrations");
+            }
+            for migration in applied {
+                println!("Applied {}", migration.full_name());
+            }
+        }
+        "revert" => match revert_latest(&conn).map_err(failed)? {
+            Some(migration) => println!("Reverted {}", migration.full_name()),
+            None => println!("No migration to revert"),
+        },
+        _ => return Err(MIGRATE_USAGE.to_string()),
+    }
+    Ok(())
+}
@@ -4,14 +4,49 @@ use diesel::result;
 
 #[derive(Debug, Display)]
 pub enum ApplicationError {
-    #[display(fmt = "{ }", _0)]
-    PasswordNotMatch(String),
     #[display(fmt = "{ }", _0)]
     WrongPassword(String),
     #[display(fmt = "{ }", _0)]
     DBError(result::Error),
     #[display(fmt = "{ }", _0)]
     HashError(BcryptError),
+    #[display(fmt = "{ }", _0)]
+    InvalidQuery(String),
+    #[display(fmt = "{ }", _0)]
+    InvalidMovement(String),
+    #[display(fmt = "{ }", _0)]
+    InsufficientStock(String),
+    #[display(fmt = "{ }", _0)]
+    EmailTaken(String),
+    #[display(fmt = "{ }", _0)]
+    CompanyTaken(String),
+    #[display(fmt = "{ }", _0)]
+    EmailNotVerified(String),
+    #[display(fmt = "{ }", _0)]
+    InvalidToken(String),
+    #[display(fmt = "{ }", _0)]
+    Csv(String),
+    #[display(fmt = "{ }", _0)]
+    InvalidCsv(String),
+    #[display(fmt = "{ }", _0)]
+    InvalidTwoFactorCode(String),
+    #[display(fmt = "{ }", _0)]
+    TwoFactorEnabled(String),
+    #[display(fmt = "{ }", _0)]
+    AccountDisabled(String),
+    #[display(fmt = "{ }", _0)]
+    InvalidCategory(String),
+    #[display(fmt = "{ }", _0)]
+    CategoryConflict(String),
+    #[display(fmt = "{ }", _0)]
+    VariantConflict(String),
+    #[display(fmt = "{ }", _0)]
+    LocationConflict(String),
+    #[display(fmt = "{ }", _0)]
+    LastOwner(String),
+    // a rotated refresh token was presented again, holds its family
+    #[display(fmt = "Refresh token reused")]
+    TokenReused(String),
 }
 
 // From BcryptError to ApplicationError
@@ -1,5 +1,15 @@
-use actix_web::{error, http::StatusCode, HttpResponse};
+use actix_web::{
+    error,
+    http::{header, StatusCode},
+    HttpResponse,
+};
 use derive_more::Display;
+use diesel::result::{DatabaseErrorKind, Error as DieselError};
+use serde::Serialize;
+use validator::ValidationErrors;
+
+use super::application_error::ApplicationError;
+use crate::utils::validation::{field_errors, FieldError};
 
 #[derive(Debug, Display)]
 pub enum ServerError {
@@ -13,16 +23,109 @@ pub enum ServerError {
     // unauthorized
     #[display(fmt = "{ }", _0)]
     Unauthorized(String),
+
+    // authenticated but not allowed
+    #[display(fmt = "{ }", _0)]
+    Forbidden(String),
+
+    // conflict with the current state of a resource
+    #[display(fmt = "{ }", _0)]
+    Conflict(String),
+
+    // well-formed request referencing something that does not exist
+    #[display(fmt = "{ }", _0)]
+    UnprocessableEntity(String),
+
+    // too many attempts, the client should retry after the given seconds
+    #[display(fmt = "{ }", _0)]
+    TooManyRequests(String, u64),
+
+    // a required resource such as a database connection is exhausted
+    #[display(fmt = "{ }", _0)]
+    ServiceUnavailable(String),
+
+    // request body failed validation, one entry per invalid field
+    #[display(fmt = "Validation failed")]
+    ValidationFailed(Vec<FieldError>),
+}
+
+// Problem details body as described in RFC 7807
+#[derive(Serialize)]
+pub struct ProblemDetails<'a> {
+    #[serde(rename = "type")]
+    pub problem_type: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+    pub code: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errors: Option<&'a [FieldError]>,
+}
+
+impl ServerError {
+    // Stable machine-readable code of the error
+    pub fn code(&self) -> &'static str {
+        match self {
+            ServerError::NotFound(_) => "not_found",
+            ServerError::BadRequest(_) => "bad_request",
+            ServerError::InternalServerError(_) => "internal_error",
+            ServerError::Unauthorized(_) => "unauthorized",
+            ServerError::Forbidden(_) => "forbidden",
+            ServerError::Conflict(_) => "conflict",
+            ServerError::UnprocessableEntity(_) => "unprocessable_entity",
+            ServerError::ValidationFailed(_) => "validation_failed",
+            ServerError::TooManyRequests(..) => "too_many_requests",
+            ServerError::ServiceUnavailable(_) => "service_unavailable",
+        }
+    }
+
+    // Message safe to show to the client. Internal errors never leak their
+    // message, it is logged instead.
+    pub fn detail(&self) -> String {
+        match self {
+            ServerError::InternalServerError(_) => {
+                "The server encountered an unexpected error".to_string()
+            }
+            ServerError::NotFound(msg)
+            | ServerError::BadRequest(msg)
+            | ServerError::Unauthorized(msg)
+            | ServerError::Forbidden(msg)
+            | ServerError::Conflict(msg)
+            | ServerError::UnprocessableEntity(msg)
+            | ServerError::TooManyRequests(msg, _)
+            | ServerError::ServiceUnavailable(msg) => msg.clone(),
+            ServerError::ValidationFailed(_) => "The request body is invalid".to_string(),
+        }
+    }
+
+    pub fn problem(&self) -> ProblemDetails<'_> {
+        let status = error::ResponseError::status_code(self);
+        ProblemDetails {
+            problem_type: "about:blank".to_string(),
+            title: status.canonical_reason().unwrap_or("Error").to_string(),
+            status: status.as_u16(),
+            detail: self.detail(),
+            code: self.code(),
+            errors: match self {
+                ServerError::ValidationFailed(errors) => Some(errors),
+                _ => None,
+            },
+        }
+    }
 }
 
 impl error::ResponseError for ServerError {
     fn error_response(&self) -> HttpResponse<actix_web::body::BoxBody> {
-        match self {
-            ServerError::NotFound(msg) => HttpResponse::NotFound().json(msg),
-            ServerError::BadRequest(msg) => HttpResponse::BadRequest().json(msg),
-            ServerError::InternalServerError(msg) => HttpResponse::InternalServerError().json(msg),
-            ServerError::Unauthorized(msg) => HttpResponse::Unauthorized().json(msg),
+        if let ServerError::InternalServerError(msg) = self {
+            log::error!("Internal server error: {}", msg);
         }
+        let mut response = HttpResponse::build(self.status_code());
+        if let ServerError::TooManyRequests(_, retry_after) = self {
+            response.insert_header((header::RETRY_AFTER, retry_after.to_string()));
+        }
+        response
+            .content_type("application/problem+json")
+            .json(self.problem())
     }
     fn status_code(&self) -> actix_web::http::StatusCode {
         match self {
@@ -30,6 +133,80 @@ impl error::ResponseError for ServerError {
             ServerError::BadRequest(_) => StatusCode::BAD_REQUEST,
             ServerError::InternalServerError(_) => StatusCode::INTERNAL_SERVER_ERROR,
             ServerError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ServerError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ServerError::Conflict(_) => StatusCode::CONFLICT,
+            ServerError::TooManyRequests(..) => StatusCode::TOO_MANY_REQUESTS,
+            ServerError::ServiceUnavailable(_) => StatusCode::SERVICE_UNAVAILABLE,
+            ServerError::UnprocessableEntity(_) | ServerError::ValidationFailed(_) => {
+                StatusCode::UNPROCESSABLE_ENTITY
+            }
+        }
+    }
+}
+
+// From diesel::result::Error to ServerError
+impl From<DieselError> for ServerError {
+    fn from(error: DieselError) -> Self {
+        match error {
+            DieselError::NotFound => {
+                ServerError::NotFound("The requested resource was not found".to_string())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                ServerError::Conflict("The resource already exists".to_string())
+            }
+            DieselError::DatabaseError(DatabaseErrorKind::ForeignKeyViolation, _) => {
+                ServerError::UnprocessableEntity(
+                    "The request references a resource that does not exist".to_string(),
+                )
+            }
+            _ => ServerError::InternalServerError(error.to_string()),
+        }
+    }
+}
+
+// The blocking thread pool went away, only happens while shutting down
+impl From<error::BlockingError> for ServerError {
+    fn from(error: error::BlockingError) -> Self {
+        ServerError::InternalServerError(error.to_string())
+    }
+}
+
+// From validator errors to ServerError
+impl From<ValidationErrors> for ServerError {
+    fn from(errors: ValidationErrors) -> Self {
+        ServerError::ValidationFailed(field_errors(&errors))
+    }
+}
+
+// From ApplicationError to ServerError
+impl From<ApplicationError> for ServerError {
+    fn from(error: ApplicationError) -> Self {
+        match error {
+            ApplicationError::DBError(err) => err.into(),
+            ApplicationError::HashError(err) => ServerError::InternalServerError(err.to_string()),
+            ApplicationError::Csv(msg) => ServerError::InternalServerError(msg),
+            ApplicationError::WrongPassword(msg) | ApplicationError::InvalidTwoFactorCode(msg) => {
+                ServerError::Unauthorized(msg)
+            }
+            ApplicationError::TokenReused(_) => {
+                ServerError::Unauthorized("Invalid or expired token".to_string())
+            }
+            ApplicationError::EmailNotVerified(msg) | ApplicationError::AccountDisabled(msg) => {
+                ServerError::Forbidden(msg)
+            }
+            ApplicationError::EmailTaken(msg)
+            | ApplicationError::CompanyTaken(msg)
+            | ApplicationError::InsufficientStock(msg)
+            | ApplicationError::TwoFactorEnabled(msg)
+            | ApplicationError::CategoryConflict(msg)
+            | ApplicationError::VariantConflict(msg)
+            | ApplicationError::LocationConflict(msg)
+            | ApplicationError::LastOwner(msg) => ServerError::Conflict(msg),
+            ApplicationError::InvalidQuery(msg)
+            | ApplicationError::InvalidMovement(msg)
+            | ApplicationError::InvalidToken(msg)
+            | ApplicationError::InvalidCsv(msg)
+            | ApplicationError::InvalidCategory(msg) => ServerError::BadRequest(msg),
         }
     }
 }
@@ -0,0 +1,42 @@
+use actix_web::{delete, get, post, web, HttpResponse};
+
+use crate::db_connection::PgPool;
+use crate::errors::server_error::ServerError;
+use crate::handlers::{run_blocking, RequireRole};
+use crate::models::api_key::{ApiKey, CreateApiKey};
+use crate::models::role::Admin;
+use crate::utils::validation::ValidatedJson;
+
+// List the api keys of the company, never includes the keys themselves
+#[get("")]
+pub async fn index(
+    user: RequireRole<Admin>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let api_keys = run_blocking(pool, move |conn| ApiKey::list(&user.company, conn)).await?;
+    Ok(HttpResponse::Ok().json(api_keys))
+}
+
+// Create an api key, the plain key is only returned in this response
+#[post("")]
+pub async fn create(
+    user: RequireRole<Admin>,
+    new_api_key: ValidatedJson<CreateApiKey>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let new_api_key = new_api_key.into_inner();
+    let api_key = run_blocking(pool, move |conn| new_api_key.create(&user, conn)).await?;
+    Ok(HttpResponse::Created().json(api_key))
+}
+
+// Revoke an api key, it stops working immediately
+#[delete("/{id}")]
+pub async fn revoke(
+    user: RequireRole<Admin>,
+    id: web::Path<i32>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let api_key = run_blocking(pool, move |conn| ApiKey::revoke(id, &user.company, conn)).await?;
+    Ok(HttpResponse::Ok().json(api_key))
+}
@@ -1,54 +1,347 @@
-use super::pg_pool_handler;
+use super::run_blocking;
+use crate::config::Settings;
+use crate::db_connection::PgPool;
+use crate::errors::application_error::ApplicationError;
 use crate::errors::server_error::ServerError;
-use crate::models::user::AuthenticateUser;
-use crate::utils::jwt::create_token;
-use crate::{db_connection::PgPool, errors::application_error::ApplicationError};
+use crate::models::login_attempt::LoginAttempt;
+use crate::models::refresh_token::{RefreshRequest, RefreshToken};
+use crate::models::revoked_token::RevokedToken;
+use crate::models::two_factor::TwoFactorLogin;
+use crate::models::user::{AuthenticateUser, User};
+use crate::utils::jwt::{
+    create_purpose_token, create_token, decode_purpose_token, decode_token,
+    ACCESS_TOKEN_TTL_MINUTES, TWO_FACTOR_PURPOSE,
+};
+use crate::utils::login_throttle::{LoginThrottle, FAILURE_WINDOW};
+use crate::utils::metrics::metrics;
 use actix_identity::Identity;
+use actix_web::rt::time::sleep;
 use actix_web::{delete, post, web, HttpMessage, HttpRequest, HttpResponse};
-use csrf::{AesGcmCsrfProtection, CsrfProtection};
-use std::sync::Mutex;
+use chrono::Duration;
+use serde::Serialize;
+use std::net::IpAddr;
+
+// How long the second step of a two-factor login may take
+const TWO_FACTOR_TOKEN_TTL_MINUTES: i64 = 5;
+
+// Body returned when a session is started or renewed
+#[derive(Serialize)]
+pub struct SessionResponse {
+    pub user: User,
+    pub refresh_token: String,
+    // lifetime of the access token in seconds
+    pub expires_in: i64,
+}
+
+// Store a new access token for the user in the identity session
+fn start_session(req: &HttpRequest, user: &User) -> Result<(), ServerError> {
+    let token = create_token(&user.email, &user.company, user.role())?;
+    Identity::login(&req.extensions(), token)
+        .map_err(|err| ServerError::InternalServerError(format!("{}", err)))?;
+    Ok(())
+}
+
+// Body returned when the password was right but a second factor is required
+#[derive(Serialize)]
+pub struct TwoFactorRequired {
+    pub two_factor_required: bool,
+    pub two_factor_token: String,
+    pub expires_in: i64,
+}
+
+// Start the session and issue a refresh token to renew it
+async fn session_response(
+    req: &HttpRequest,
+    user: User,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    // create jwt token and the refresh token to renew it
+    start_session(req, &user)?;
+    let (refresh_token, user) = run_blocking(pool, move |conn| {
+        RefreshToken::issue(&user, conn).map(|refresh_token| (refresh_token, user))
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(SessionResponse {
+        user,
+        refresh_token,
+        expires_in: ACCESS_TOKEN_TTL_MINUTES * 60,
+    }))
+}
+
+// Audit a login attempt and count it by outcome. A failed audit is logged
+// rather than failing the login.
+async fn record_attempt(
+    pool: &web::Data<PgPool>,
+    email: &str,
+    ip: &str,
+    reason: Option<&'static str>,
+) {
+    metrics()
+        .login_attempts
+        .with_label_values(&[reason.unwrap_or("success")])
+        .inc();
+    let (email, ip) = (email.to_string(), ip.to_string());
+    let recorded = run_blocking(pool.clone(), move |conn| {
+        LoginAttempt::new(&email, &ip, reason).record(conn)
+    })
+    .await;
+    if let Err(err) = recorded {
+        log::error!("Failed to record a login attempt: {}", err);
+    }
+}
+
+// Address of the client. X-Forwarded-For is only believed when the request
+// comes through one of `server.trusted_proxies`, anyone else could claim a
+// new address with every attempt and never be locked out.
+pub fn client_ip(req: &HttpRequest, trusted_proxies: &[IpAddr]) -> String {
+    let forwarded_for = req
+        .headers()
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok());
+    resolve_client_ip(
+        req.peer_addr().map(|addr| addr.ip()),
+        forwarded_for,
+        trusted_proxies,
+    )
+    .map(|ip| ip.to_string())
+    .unwrap_or_else(|| "unknown".to_string())
+}
+
+// Walk X-Forwarded-For from the nearest hop while it is a trusted proxy, the
+// first other address is the client. Entries further left were written by the
+// client itself.
+fn resolve_client_ip(
+    peer: Option<IpAddr>,
+    forwarded_for: Option<&str>,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let mut client = peer?;
+    if !trusted_proxies.contains(&client) {
+        return Some(client);
+    }
+    let hops = forwarded_for.unwrap_or_default().rsplit(',');
+    for hop in hops {
+        match hop.trim().parse::<IpAddr>() {
+            Ok(ip) => {
+                client = ip;
+                if !trusted_proxies.contains(&ip) {
+                    break;
+                }
+            }
+            // an unreadable entry ends the trusted chain
+            Err(_) => break,
+        }
+    }
+    Some(client)
+}
 
 #[post("/login")]
 pub async fn login(
     req: HttpRequest,
     auth_user: web::Json<AuthenticateUser>,
     pool: web::Data<PgPool>,
-    generator: web::Data<Mutex<AesGcmCsrfProtection>>,
+    throttle: web::Data<LoginThrottle>,
+    settings: web::Data<Settings>,
 ) -> Result<HttpResponse, ServerError> {
-    let generator = generator.lock().unwrap();
-    // handle pool
-    let pg_pool = pg_pool_handler(pool)?;
-    // login user
-    let user = auth_user.login(&pg_pool).map_err(|err| {
-        // match server error
-        match err {
-            // if error is not found, return bad request
-            ApplicationError::DBError(diesel::result::Error::NotFound) => {
-                ServerError::BadRequest(format!("{}", err))
-            }
-            // if error is internal server error, return internal server error
-            _ => ServerError::InternalServerError(format!("{}", err)),
+    let ip = client_ip(&req, &settings.server.trusted_proxies);
+    let auth_user = auth_user.into_inner();
+
+    // refuse locked accounts and ips, slow down repeated failures
+    let failures = throttle.failures(&auth_user.email, &ip).await;
+    if failures.is_locked() {
+        record_attempt(&pool, &auth_user.email, &ip, Some("locked")).await;
+        return Err(ServerError::TooManyRequests(
+            "Too many failed login attempts, try again later".to_string(),
+            FAILURE_WINDOW.as_secs(),
+        ));
+    }
+    let delay = failures.delay();
+    if !delay.is_zero() {
+        sleep(delay).await;
+    }
+
+    // login user, bcrypt runs on the blocking pool along with the query
+    let email = auth_user.email.clone();
+    let login = run_blocking(pool.clone(), move |conn| {
+        Ok::<_, ServerError>(auth_user.login(conn))
+    })
+    .await?;
+    let user = match login {
+        Ok(user) => user,
+        Err(err) => {
+            let reason = match err {
+                ApplicationError::WrongPassword(_) => {
+                    throttle.record_failure(&email, &ip).await;
+                    "wrong_credentials"
+                }
+                ApplicationError::EmailNotVerified(_) => "email_not_verified",
+                ApplicationError::AccountDisabled(_) => "account_disabled",
+                _ => "error",
+            };
+            record_attempt(&pool, &email, &ip, Some(reason)).await;
+            return Err(err.into());
         }
-    })?;
+    };
 
-    // create jwt token
-    let token = create_token(&user.email, &user.company)?;
-    Identity::login(&req.extensions(), token)
-        .map_err(|err| ServerError::InternalServerError(format!("{}", err)))?;
+    // no session yet when a second factor is required, only a short lived token
+    // that can be exchanged for one at /auth/login/2fa
+    if user.two_factor_enabled() {
+        record_attempt(&pool, &email, &ip, Some("two_factor_required")).await;
+        let two_factor_token = create_purpose_token(
+            &user.email,
+            TWO_FACTOR_PURPOSE,
+            Duration::minutes(TWO_FACTOR_TOKEN_TTL_MINUTES),
+        )?;
+        return Ok(HttpResponse::Ok().json(TwoFactorRequired {
+            two_factor_required: true,
+            two_factor_token,
+            expires_in: TWO_FACTOR_TOKEN_TTL_MINUTES * 60,
+        }));
+    }
 
-    // Response has csrf token for security
-    let (csrf_token, csrf_cookie) = generator
-        .generate_token_pair(None, 300)
-        .expect("Failed to generate token");
-    let response = HttpResponse::Ok()
-        .append_header(("x-csrf-token", csrf_token.b64_string()))
-        .append_header(("x-csrf-token-cookie", csrf_cookie.b64_string()))
-        .json(user);
-    Ok(response)
+    throttle.record_success(&email).await;
+    record_attempt(&pool, &email, &ip, None).await;
+    session_response(&req, user, pool).await
 }
 
+// Complete a two-factor login with a code from the authenticator app or a
+// recovery code
+#[post("/login/2fa")]
+pub async fn login_two_factor(
+    req: HttpRequest,
+    two_factor_login: web::Json<TwoFactorLogin>,
+    pool: web::Data<PgPool>,
+    throttle: web::Data<LoginThrottle>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, ServerError> {
+    let email = decode_purpose_token(&two_factor_login.two_factor_token, TWO_FACTOR_PURPOSE)
+        .map_err(|_| ServerError::Unauthorized("Invalid or expired token".to_string()))?;
+    let ip = client_ip(&req, &settings.server.trusted_proxies);
+
+    // wrong codes count towards the same lockout as wrong passwords
+    let failures = throttle.failures(&email, &ip).await;
+    if failures.is_locked() {
+        record_attempt(&pool, &email, &ip, Some("locked")).await;
+        return Err(ServerError::TooManyRequests(
+            "Too many failed login attempts, try again later".to_string(),
+            FAILURE_WINDOW.as_secs(),
+        ));
+    }
+    let delay = failures.delay();
+    if !delay.is_zero() {
+        sleep(delay).await;
+    }
+
+    let code = two_factor_login.into_inner().code;
+    let user_email = email.clone();
+    let verified = run_blocking(pool.clone(), move |conn| {
+        Ok::<_, ServerError>(User::verify_two_factor(&user_email, &code, conn))
+    })
+    .await?;
+    let user = match verified {
+        Ok(user) => user,
+        Err(err) => {
+            let reason = match err {
+                ApplicationError::InvalidTwoFactorCode(_) => {
+                    throttle.record_failure(&email, &ip).await;
+                    "wrong_two_factor_code"
+                }
+                _ => "error",
+            };
+            record_attempt(&pool, &email, &ip, Some(reason)).await;
+            return Err(err.into());
+        }
+    };
+    throttle.record_success(&email).await;
+    record_attempt(&pool, &email, &ip, None).await;
+    session_response(&req, user, pool).await
+}
+
+// Exchange a refresh token for a new access token and refresh token
+#[post("/refresh")]
+pub async fn refresh(
+    req: HttpRequest,
+    refresh_request: web::Json<RefreshRequest>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let token = refresh_request.into_inner().refresh_token;
+    let (refresh_token, user) = run_blocking(pool, move |conn| {
+        RefreshToken::rotate(&token, conn).map_err(|err| match err {
+            ApplicationError::InvalidToken(msg) => ServerError::Unauthorized(msg),
+            _ => err.into(),
+        })
+    })
+    .await?;
+
+    start_session(&req, &user)?;
+    Ok(HttpResponse::Ok().json(SessionResponse {
+        user,
+        refresh_token,
+        expires_in: ACCESS_TOKEN_TTL_MINUTES * 60,
+    }))
+}
+
+// Revoke the current access token and, when given, the refresh token family
 #[delete("/logout")]
-pub async fn logout(id: Identity) -> Result<HttpResponse, ServerError> {
+pub async fn logout(
+    id: Identity,
+    refresh_request: Option<web::Json<RefreshRequest>>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    // an already expired access token does not need to be revoked
+    let session = id.id().ok().and_then(|token| decode_token(&token).ok());
+    let refresh_token = refresh_request.map(|request| request.into_inner().refresh_token);
+    run_blocking(pool, move |conn| -> Result<(), ApplicationError> {
+        if let Some(user) = session {
+            RevokedToken::revoke(&user.token_id, user.expires_at, conn)?;
+        }
+        if let Some(refresh_token) = refresh_token {
+            RefreshToken::revoke(&refresh_token, conn)?;
+        }
+        Ok(())
+    })
+    .await?;
     id.logout();
     Ok(HttpResponse::Ok().into())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn ignores_forwarded_headers_from_untrusted_peers() {
+        let client = resolve_client_ip(Some(ip("203.0.113.7")), Some("198.51.100.1"), &[]);
+        assert_eq!(client, Some(ip("203.0.113.7")));
+    }
+
+    #[test]
+    fn takes_the_nearest_untrusted_hop_behind_trusted_proxies() {
+        let proxies = [ip("10.0.0.1"), ip("10.0.0.2")];
+        // the client made up the leftmost entry
+        let client = resolve_client_ip(
+            Some(ip("10.0.0.1")),
+            Some("1.2.3.4, 198.51.100.9, 10.0.0.2"),
+            &proxies,
+        );
+        assert_eq!(client, Some(ip("198.51.100.9")));
+    }
+
+    #[test]
+    fn falls_back_to_the_proxy_without_a_usable_header() {
+        let proxies = [ip("10.0.0.1")];
+        assert_eq!(
+            resolve_client_ip(Some(ip("10.0.0.1")), None, &proxies),
+            Some(ip("10.0.0.1"))
+        );
+        assert_eq!(
+            resolve_client_ip(Some(ip("10.0.0.1")), Some("garbage"), &proxies),
+            Some(ip("10.0.0.1"))
+        );
+        assert_eq!(resolve_client_ip(None, Some("1.2.3.4"), &proxies), None);
+    }
+}
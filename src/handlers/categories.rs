@@ -0,0 +1,98 @@
+use actix_web::{delete, get, post, put, web, HttpResponse};
+
+use crate::db_connection::PgPool;
+use crate::errors::server_error::ServerError;
+use crate::handlers::{run_blocking, LoggedUser, RequireRole, RequireScope};
+use crate::models::api_key::{ReadProducts, WriteProducts};
+use crate::models::category::{Category, CategoryAssignment, NewCategory};
+use crate::models::role::Editor;
+use crate::utils::validation::ValidatedJson;
+
+// List every category of the company, each parent right before its subtree
+#[get("")]
+pub async fn index(user: LoggedUser, pool: web::Data<PgPool>) -> Result<HttpResponse, ServerError> {
+    let categories = run_blocking(pool, move |conn| Category::list(&user.company, conn)).await?;
+    Ok(HttpResponse::Ok().json(categories))
+}
+
+// Create a category, under `parent_id` when given
+#[post("")]
+pub async fn create(
+    user: RequireRole<Editor>,
+    new_category: ValidatedJson<NewCategory>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let new_category = new_category.into_inner();
+    let category = run_blocking(pool, move |conn| new_category.create(&user, conn)).await?;
+    Ok(HttpResponse::Created().json(category))
+}
+
+// Get a category by id
+#[get("/{id}")]
+pub async fn get(
+    user: LoggedUser,
+    id: web::Path<i32>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let category = run_blocking(pool, move |conn| Category::find(id, &user.company, conn)).await?;
+    Ok(HttpResponse::Ok().json(category))
+}
+
+// Rename a category or move it, with its subcategories, under another parent
+#[put("/{id}")]
+pub async fn update(
+    user: RequireRole<Editor>,
+    id: web::Path<i32>,
+    new_category: ValidatedJson<NewCategory>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let new_category = new_category.into_inner();
+    let category = run_blocking(pool, move |conn| new_category.update(id, &user, conn)).await?;
+    Ok(HttpResponse::Ok().json(category))
+}
+
+// Delete a category that has no subcategories
+#[delete("/{id}")]
+pub async fn destroy(
+    user: RequireRole<Editor>,
+    id: web::Path<i32>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    run_blocking(pool, move |conn| Category::destroy(id, &user.company, conn)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
+
+// Categories of a product, registered under /products
+#[get("/{id}/categories")]
+pub async fn for_product(
+    user: RequireScope<ReadProducts>,
+    id: web::Path<i32>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let categories = run_blocking(pool, move |conn| {
+        Category::for_product(id, &user.company, conn)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(categories))
+}
+
+// Replace the categories of a product, registered under /products
+#[put("/{id}/categories")]
+pub async fn assign(
+    user: RequireScope<WriteProducts>,
+    id: web::Path<i32>,
+    assignment: web::Json<CategoryAssignment>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let assignment = assignment.into_inner();
+    let categories = run_blocking(pool, move |conn| {
+        Category::assign(id, &assignment, &user, conn)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(categories))
+}
@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::time::{Duration, Instant};
+
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::rt::time::timeout;
+use actix_web::{get, web, HttpResponse};
+use diesel::RunQueryDsl;
+use serde::Serialize;
+
+use crate::db_connection::PgPool;
+
+// A dependency slower than this counts as down
+pub const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Up,
+    Down,
+}
+
+#[derive(Serialize)]
+pub struct Check {
+    pub status: Status,
+    pub latency_ms: u128,
+}
+
+#[derive(Serialize)]
+pub struct Health {
+    pub status: Status,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub checks: BTreeMap<&'static str, Check>,
+}
+
+fn no_store(status: Status) -> actix_web::HttpResponseBuilder {
+    let mut builder = match status {
+        Status::Up => HttpResponse::Ok(),
+        Status::Down => HttpResponse::ServiceUnavailable(),
+    };
+    builder.insert_header(CacheControl(vec![CacheDirective::NoStore]));
+    builder
+}
+
+// Run a check with the readiness timeout, failures are logged and reported as down
+async fn check<F>(name: &str, probe: F) -> Check
+where
+    F: Future<Output = Result<(), String>>,
+{
+    let started = Instant::now();
+    let result = match timeout(READINESS_TIMEOUT, probe).await {
+        Ok(result) => result,
+        Err(_) => Err(format!("timed out after {:?}", READINESS_TIMEOUT)),
+    };
+    let status = match result {
+        Ok(()) => Status::Up,
+        Err(err) => {
+            log::warn!("Readiness check {} failed: {}", name, err);
+            Status::Down
+        }
+    };
+    Check {
+        status,
+        latency_ms: started.elapsed().as_millis(),
+    }
+}
+
+async fn check_database(pool: web::Data<PgPool>) -> Result<(), String> {
+    web::block(move || {
+        let conn = pool
+            .get_timeout(READINESS_TIMEOUT)
+            .map_err(|err| err.to_string())?;
+        diesel::sql_query("SELECT 1")
+            .execute(&conn)
+            .map(|_| ())
+            .map_err(|err| err.to_string())
+    })
+    .await
+    .map_err(|err| err.to_string())?
+}
+
+async fn check_redis(client: web::Data<redis::Client>) -> Result<(), String> {
+    let mut conn = client
+        .get_async_connection()
+        .await
+        .map_err(|err| err.to_string())?;
+    redis::cmd("PING")
+        .query_async::<_, String>(&mut conn)
+        .await
+        .map(|_| ())
+        .map_err(|err| err.to_string())
+}
+
+// The process is running and able to answer requests
+#[get("/live")]
+pub async fn live() -> HttpResponse {
+    no_store(Status::Up).json(Health {
+        status: Status::Up,
+        checks: BTreeMap::new(),
+    })
+}
+
+// Every dependency needed to serve traffic is reachable, 503 otherwise
+#[get("/ready")]
+pub async fn ready(
+    pool: web::Data<PgPool>,
+    redis_client: web::Data<redis::Client>,
+) -> HttpResponse {
+    let (database, redis) = futures_util::join!(
+        check("database", check_database(pool)),
+        check("redis", check_redis(redis_client)),
+    );
+    let checks = BTreeMap::from([("database", database), ("redis", redis)]);
+    let status = if checks.values().all(|check| check.status == Status::Up) {
+        Status::Up
+    } else {
+        Status::Down
+    };
+    no_store(status).json(Health { status, checks })
+}
@@ -0,0 +1,65 @@
+use actix_web::{delete, get, post, put, web, HttpResponse};
+
+use crate::db_connection::PgPool;
+use crate::errors::server_error::ServerError;
+use crate::handlers::{run_blocking, LoggedUser, RequireRole};
+use crate::models::location::{Location, NewLocation};
+use crate::models::role::Editor;
+use crate::utils::validation::ValidatedJson;
+
+// List the locations of the company by name
+#[get("")]
+pub async fn index(user: LoggedUser, pool: web::Data<PgPool>) -> Result<HttpResponse, ServerError> {
+    let locations = run_blocking(pool, move |conn| Location::list(&user.company, conn)).await?;
+    Ok(HttpResponse::Ok().json(locations))
+}
+
+// Create a location
+#[post("")]
+pub async fn create(
+    user: RequireRole<Editor>,
+    new_location: ValidatedJson<NewLocation>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let new_location = new_location.into_inner();
+    let location = run_blocking(pool, move |conn| new_location.create(&user, conn)).await?;
+    Ok(HttpResponse::Created().json(location))
+}
+
+// Get a location by id
+#[get("/{id}")]
+pub async fn get(
+    user: LoggedUser,
+    id: web::Path<i32>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let location = run_blocking(pool, move |conn| Location::find(id, &user.company, conn)).await?;
+    Ok(HttpResponse::Ok().json(location))
+}
+
+// Rename a location
+#[put("/{id}")]
+pub async fn update(
+    user: RequireRole<Editor>,
+    id: web::Path<i32>,
+    new_location: ValidatedJson<NewLocation>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let new_location = new_location.into_inner();
+    let location = run_blocking(pool, move |conn| new_location.update(id, &user, conn)).await?;
+    Ok(HttpResponse::Ok().json(location))
+}
+
+// Delete a location that holds no stock
+#[delete("/{id}")]
+pub async fn destroy(
+    user: RequireRole<Editor>,
+    id: web::Path<i32>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    run_blocking(pool, move |conn| Location::destroy(id, &user.company, conn)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,66 @@
+use actix_web::http::header;
+use actix_web::{get, web, HttpRequest, HttpResponse};
+
+use crate::config::Settings;
+use crate::db_connection::PgPool;
+use crate::errors::server_error::ServerError;
+use crate::models::product::Product;
+use crate::utils::metrics::metrics;
+
+use super::run_blocking;
+
+// Only scrapers holding the configured token may read the metrics, they name
+// every company. Without a token the endpoint does not exist.
+fn authorize(req: &HttpRequest, token: &str) -> Result<(), ServerError> {
+    if token.is_empty() {
+        return Err(ServerError::NotFound("Not found".to_string()));
+    }
+    let presented = req
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::trim)
+        .unwrap_or_default();
+    ring::constant_time::verify_slices_are_equal(presented.as_bytes(), token.as_bytes())
+        .map_err(|_| ServerError::Unauthorized("Invalid metrics token".to_string()))
+}
+
+// Metrics in the prometheus text format. Pool gauges are sampled when
+// scraped, stock gauges at most once per sampling interval.
+#[get("/metrics")]
+pub async fn index(
+    req: HttpRequest,
+    settings: web::Data<Settings>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    authorize(&req, &settings.metrics.token)?;
+    let metrics = metrics();
+    let state = pool.state();
+    metrics.db_pool_connections.set(state.connections as i64);
+    metrics
+        .db_pool_idle_connections
+        .set(state.idle_connections as i64);
+
+    if metrics.stock_sample_due() {
+        let stock = run_blocking(pool, Product::stock_by_company).await?;
+        // companies without products must disappear from the output
+        metrics.company_stock.reset();
+        metrics.company_products_out_of_stock.reset();
+        for company in stock {
+            metrics
+                .company_stock
+                .with_label_values(&[company.company.as_str()])
+                .set(company.total);
+            metrics
+                .company_products_out_of_stock
+                .with_label_values(&[company.company.as_str()])
+                .set(company.out_of_stock);
+        }
+    }
+
+    let body = metrics.encode().map_err(ServerError::InternalServerError)?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(body))
+}
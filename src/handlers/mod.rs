@@ -1,122 +1,319 @@
+use std::marker::PhantomData;
+use std::ops::Deref;
+
 use actix_identity::Identity;
-use actix_utils::future::{ready, Ready};
-use actix_web::{web, FromRequest};
-use csrf::{AesGcmCsrfProtection, CsrfProtection};
-use data_encoding::BASE64;
+use actix_web::{http::header, web, FromRequest, HttpMessage, HttpRequest};
+use diesel::PgConnection;
+use futures_util::future::{ready, LocalBoxFuture};
 
 use crate::{
     db_connection::{PgPool, PgPooledConnection},
-    errors::server_error::ServerError,
-    utils::jwt::{decode_token, SlimUser},
+    errors::{application_error::ApplicationError, server_error::ServerError},
+    models::{
+        api_key::{ApiKey, RequiredScope},
+        revoked_token::RevokedToken,
+        role::MinimumRole,
+        user::User,
+    },
+    utils::{
+        csrf::ApiKeyUser,
+        jwt::{decode_token, SlimUser},
+    },
 };
 
 pub type LoggedUser = SlimUser;
 
+pub mod api_keys;
 pub mod authentication;
+pub mod categories;
+pub mod health;
+pub mod locations;
+pub mod metrics;
+pub mod password;
+pub mod product_csv;
+pub mod product_variants;
 pub mod products;
 pub mod register;
+pub mod stock_movements;
+pub mod two_factor;
+pub mod well_known;
 
+// Check out a connection, waiting at most the configured connection timeout.
+// Blocks, so call it from `run_blocking` rather than on an async worker.
 pub fn pg_pool_handler(pool: web::Data<PgPool>) -> Result<PgPooledConnection, ServerError> {
-    pool.get()
-        .map_err(|e| ServerError::InternalServerError(e.to_string()))
+    pool.get().map_err(|e| {
+        log::warn!("No database connection available: {}", e);
+        ServerError::ServiceUnavailable("The service is busy, please try again later".to_string())
+    })
+}
+
+// Run blocking work such as diesel queries and bcrypt on the blocking thread
+// pool with a pooled connection, keeping the async workers free
+pub async fn run_blocking<F, T, E>(pool: web::Data<PgPool>, work: F) -> Result<T, ServerError>
+where
+    F: FnOnce(&PgConnection) -> Result<T, E> + Send + 'static,
+    T: Send + 'static,
+    E: Into<ServerError> + Send + 'static,
+{
+    web::block(move || {
+        let conn = pg_pool_handler(pool)?;
+        work(&conn).map_err(Into::into)
+    })
+    .await?
+}
+
+fn request_pool(req: &HttpRequest) -> Result<web::Data<PgPool>, ServerError> {
+    req.app_data::<web::Data<PgPool>>()
+        .cloned()
+        .ok_or_else(|| ServerError::InternalServerError("No database pool".to_string()))
+}
+
+// Authenticate a request carrying `Authorization: Bearer <api key>`
+async fn api_key_user(
+    pool: web::Data<PgPool>,
+    authorization: &str,
+) -> Result<LoggedUser, ServerError> {
+    let key = authorization
+        .strip_prefix("Bearer ")
+        .map(|key| key.trim().to_string())
+        .ok_or_else(|| ServerError::Unauthorized("Invalid authorization header".to_string()))?;
+    run_blocking(pool, move |conn| match ApiKey::authenticate(&key, conn) {
+        Ok(api_key) => Ok(api_key.into()),
+        Err(ApplicationError::InvalidToken(msg)) => Err(ServerError::Unauthorized(msg)),
+        Err(e) => Err(e.into()),
+    })
+    .await
+}
+
+// Reject tokens that were revoked or issued before the user's sessions were
+// invalidated
+async fn session_user(pool: web::Data<PgPool>, token: SlimUser) -> Result<LoggedUser, ServerError> {
+    run_blocking(pool, move |conn| {
+        let revoked = RevokedToken::is_revoked(&token.token_id, conn)?;
+        let valid = match User::session_is_valid(&token.email, token.issued_at, conn) {
+            Ok(valid) => valid,
+            Err(ApplicationError::DBError(diesel::result::Error::NotFound)) => false,
+            Err(e) => return Err(e.into()),
+        };
+        if revoked || !valid {
+            return Err(ServerError::Unauthorized(
+                "Session is no longer valid".to_string(),
+            ));
+        }
+        Ok(token)
+    })
+    .await
+}
+
+// Any authenticated caller, a user session or an API key
+fn authenticate(
+    req: &HttpRequest,
+    payload: &mut actix_web::dev::Payload,
+) -> LocalBoxFuture<'static, Result<SlimUser, ServerError>> {
+    // the csrf middleware already checked the key of an unsafe request
+    if let Some(ApiKeyUser(user)) = req.extensions().get::<ApiKeyUser>() {
+        return Box::pin(ready(Ok(user.clone())));
+    }
+    let pool = request_pool(req);
+
+    // API keys authenticate machine clients, sessions use the identity cookie
+    if let Some(authorization) = req.headers().get(header::AUTHORIZATION) {
+        let authorization = authorization.to_str().map(str::to_string);
+        return Box::pin(async move {
+            let authorization = authorization.map_err(|_| {
+                ServerError::Unauthorized("Invalid authorization header".to_string())
+            })?;
+            api_key_user(pool?, &authorization).await
+        });
+    }
+
+    // get user from token
+    let token = Identity::from_request(req, payload)
+        .into_inner()
+        .ok()
+        .and_then(|identity| identity.id().ok());
+    Box::pin(async move {
+        let token = token.ok_or_else(|| ServerError::Unauthorized("User not found".to_string()))?;
+        let user = decode_token(&token)?;
+        session_user(pool?, user).await
+    })
 }
 
+// A user session. API keys are refused here, they only reach the endpoints
+// taking `RequireScope`.
 impl FromRequest for LoggedUser {
     type Error = ServerError;
-    type Future = Ready<Result<Self, Self::Error>>;
-
-    fn from_request(
-        req: &actix_web::HttpRequest,
-        payload: &mut actix_web::dev::Payload,
-    ) -> Self::Future {
-        let req = req.clone();
-
-        let generator = req.app_data::<AesGcmCsrfProtection>().ok_or(ready(Err(
-            ServerError::InternalServerError("No CSRF protection".to_string()),
-        )));
-        let generator = match generator {
-            Ok(g) => g,
-            Err(e) => return e,
-        };
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
 
-        let crsf_token =
-            req.headers()
-                .get("x-csrf-token")
-                .ok_or(ready(Err(ServerError::Unauthorized(
-                    "No CSRF token".to_string(),
-                ))));
-        let crsf_token = match crsf_token {
-            Ok(t) => t,
-            Err(e) => return e,
-        };
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let user = authenticate(req, payload);
+        Box::pin(async move {
+            let user = user.await?;
+            if user.api_key_id.is_some() {
+                return Err(ServerError::Forbidden(
+                    "API keys cannot be used for this endpoint".to_string(),
+                ));
+            }
+            Ok(user)
+        })
+    }
+}
 
-        let crsf_cookie =
-            req.headers()
-                .get("x-csrf-token-cookie")
-                .ok_or(ready(Err(ServerError::Unauthorized(
-                    "No CSRF token cookie".to_string(),
-                ))));
-        let crsf_cookie = match crsf_cookie {
-            Ok(t) => t,
-            Err(e) => return e,
-        };
+// Extractor for a logged user holding at least the role `R`, answers 403
+// otherwise. Derefs to the `LoggedUser`.
+pub struct RequireRole<R: MinimumRole> {
+    pub user: LoggedUser,
+    role: PhantomData<R>,
+}
 
-        // convert token into bytes
-        let crsf_token_bytes = BASE64
-            .decode(crsf_token.as_bytes())
-            .map_err(|_| ServerError::Unauthorized("Invalid CSRF token".to_string()));
-        let crsf_token_bytes = match crsf_token_bytes {
-            Ok(t) => t,
-            Err(e) => return ready(Err(e)),
-        };
+impl<R: MinimumRole> Deref for RequireRole<R> {
+    type Target = LoggedUser;
 
-        // convert cookie into bytes
-        let crsf_cookie_bytes = BASE64
-            .decode(crsf_cookie.as_bytes())
-            .map_err(|_| ServerError::Unauthorized("Invalid CSRF token cookie".to_string()));
-        let crsf_cookie_bytes = match crsf_cookie_bytes {
-            Ok(t) => t,
-            Err(e) => return ready(Err(e)),
-        };
+    fn deref(&self) -> &LoggedUser {
+        &self.user
+    }
+}
 
-        let parse_token = generator
-            .parse_token(&crsf_token_bytes)
-            .expect("Failed to parse token");
-
-        let parse_cookie = generator
-            .parse_cookie(&crsf_cookie_bytes)
-            .expect("Failed to parse cookie");
-
-        // verify token
-        let result = generator.verify_token_pair(&parse_token, &parse_cookie);
-        if !result {
-            // return unauthorized if token is not valid
-            return ready(Err(ServerError::Unauthorized(
-                "Invalid CSRF token/cookie pair".to_string(),
-            )));
-        }
+impl<R: MinimumRole> FromRequest for RequireRole<R> {
+    type Error = ServerError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
 
-        // get user from token
-        let user = Identity::from_request(&req, payload);
-        let identity = match user.into_inner() {
-            Ok(u) => u,
-            Err(_) => return ready(Err(ServerError::Unauthorized("User not found".to_string()))),
-        };
-        // decode
-        if let Ok(token) = identity.id() {
-            let token = decode_token(&token);
-            let token = match token {
-                Ok(t) => t,
-                Err(e) => return ready(Err(e)),
-            };
-            // return user if token is valid
-            return ready(Ok(token));
-        } else {
-            return ready(Err(ServerError::Unauthorized("User not found".to_string())));
-        }
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let user = LoggedUser::from_request(req, payload);
+        Box::pin(async move {
+            let user = user.await?;
+            if user.role < R::ROLE {
+                return Err(ServerError::Forbidden(format!(
+                    "Requires the {} role",
+                    R::ROLE
+                )));
+            }
+            Ok(RequireRole {
+                user,
+                role: PhantomData,
+            })
+        })
+    }
+}
+
+// Extractor for an API key granted the scope `S`, or a user session holding
+// the role that goes with it, e.g. `RequireScope<WriteProducts>` accepts
+// `products:write` keys and editors. Derefs to the `LoggedUser`.
+pub struct RequireScope<S: RequiredScope> {
+    pub user: LoggedUser,
+    scope: PhantomData<S>,
+}
+
+impl<S: RequiredScope> Deref for RequireScope<S> {
+    type Target = LoggedUser;
+
+    fn deref(&self) -> &LoggedUser {
+        &self.user
+    }
+}
+
+impl<S: RequiredScope> FromRequest for RequireScope<S> {
+    type Error = ServerError;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let user = authenticate(req, payload);
+        Box::pin(async move {
+            let user = user.await?;
+            if user.api_key_id.is_some() {
+                if !user.api_scopes.iter().any(|scope| scope.grants(S::SCOPE)) {
+                    return Err(ServerError::Forbidden(format!(
+                        "Requires the {} scope",
+                        S::SCOPE
+                    )));
+                }
+            } else if user.role < S::ROLE {
+                return Err(ServerError::Forbidden(format!(
+                    "Requires the {} role",
+                    S::ROLE
+                )));
+            }
+            Ok(RequireScope {
+                user,
+                scope: PhantomData,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use std::time::Duration;
+
+    use super::*;
+    use crate::models::api_key::ApiScope;
+    use crate::models::role::Role;
+
+    // A pool that never connects, a handler past its guards answers 503
+    fn unreachable_pool() -> web::Data<PgPool> {
+        let manager = ConnectionManager::<PgConnection>::new("postgres://127.0.0.1:1/none");
+        web::Data::new(
+            Pool::builder()
+                .connection_timeout(Duration::from_millis(50))
+                .build_unchecked(manager),
+        )
+    }
+
+    // A key as the csrf middleware hands it over once validated
+    fn key_user(scope: ApiScope) -> ApiKeyUser {
+        ApiKeyUser(SlimUser {
+            email: "scanner@x.io".to_string(),
+            company: "acme".to_string(),
+            issued_at: 0,
+            expires_at: usize::MAX,
+            token_id: "prefix".to_string(),
+            role: Role::Viewer,
+            api_key_id: Some(1),
+            api_scopes: vec![scope],
+        })
+    }
+
+    async fn status_with_key(scope: ApiScope, req: test::TestRequest) -> StatusCode {
+        let app = test::init_service(
+            App::new().app_data(unreachable_pool()).service(
+                web::scope("/products")
+                    .service(product_variants::index)
+                    .service(stock_movements::transfer),
+            ),
+        )
+        .await;
+        let req = req.to_request();
+        req.extensions_mut().insert(key_user(scope));
+        test::call_service(&app, req).await.status()
+    }
+
+    fn transfer() -> test::TestRequest {
+        test::TestRequest::post()
+            .uri("/products/1/transfers")
+            .set_json(serde_json::json!({"to_location_id": 2, "quantity": 1.0, "reason": "shelve"}))
+    }
+
+    #[actix_web::test]
+    async fn scoped_keys_reach_variants_and_transfers() {
+        let variants = test::TestRequest::get().uri("/products/1/variants");
+        assert_eq!(
+            status_with_key(ApiScope::ProductsRead, variants).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
+        assert_eq!(
+            status_with_key(ApiScope::ProductsWrite, transfer()).await,
+            StatusCode::SERVICE_UNAVAILABLE
+        );
     }
 
-    fn extract(req: &actix_web::HttpRequest) -> Self::Future {
-        Self::from_request(req, &mut actix_web::dev::Payload::None)
+    #[actix_web::test]
+    async fn read_keys_cannot_transfer() {
+        assert_eq!(
+            status_with_key(ApiScope::ProductsRead, transfer()).await,
+            StatusCode::FORBIDDEN
+        );
     }
 }
@@ -0,0 +1,68 @@
+use actix_web::{post, web, HttpResponse};
+
+use crate::{
+    config::Settings,
+    db_connection::PgPool,
+    errors::server_error::ServerError,
+    models::password_reset::{ForgotPassword, ResetPassword, RESET_TOKEN_TTL_MINUTES},
+    utils::{
+        mailer::{send_blocking, Email, Mailer},
+        validation::ValidatedJson,
+    },
+};
+
+use super::run_blocking;
+
+// Request a password reset email. Always answers 202, and does so before
+// the token is issued and mailed, so neither the body nor the response time
+// tell which emails are registered.
+#[post("/password/forgot")]
+pub async fn forgot(
+    forgot_password: ValidatedJson<ForgotPassword>,
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, ServerError> {
+    let forgot_password = forgot_password.into_inner();
+    actix_web::rt::spawn(async move {
+        let (token, user) = match run_blocking(pool, move |conn| forgot_password.issue(conn)).await
+        {
+            Ok(Some(issued)) => issued,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("Failed to issue a password reset token: {}", err);
+                return;
+            }
+        };
+        let email = Email {
+            to: user.email.clone(),
+            subject: "Reset your password".to_string(),
+            body: format!(
+                "Use the following token to reset your password within {} minutes:\n{}\n\n{}/auth/password/reset",
+                RESET_TOKEN_TTL_MINUTES,
+                token,
+                settings.server.app_url
+            ),
+        };
+        if let Err(err) = send_blocking(mailer, email).await {
+            log::error!(
+                "Failed to send password reset email to {}: {}",
+                user.email,
+                err
+            );
+        }
+    });
+
+    Ok(HttpResponse::Accepted().finish())
+}
+
+// Set a new password using a reset token
+#[post("/password/reset")]
+pub async fn reset(
+    reset_password: ValidatedJson<ResetPassword>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let reset_password = reset_password.into_inner();
+    run_blocking(pool, move |conn| reset_password.reset(conn)).await?;
+    Ok(HttpResponse::NoContent().finish())
+}
@@ -0,0 +1,121 @@
+use std::io::{self, Read};
+
+use actix_web::http::header::{ContentDisposition, DispositionParam, DispositionType};
+use actix_web::web::Bytes;
+use actix_web::{get, post, web, HttpResponse};
+use futures_util::{stream, StreamExt};
+use serde::Deserialize;
+use tokio::sync::mpsc::{channel, Receiver};
+
+use crate::db_connection::PgPool;
+use crate::errors::server_error::ServerError;
+use crate::handlers::{pg_pool_handler, run_blocking, RequireScope};
+use crate::models::api_key::{ReadProducts, WriteProducts};
+use crate::models::product_csv::{self, export_batch, write_rows, ImportMode, EXPORT_BATCH_SIZE};
+
+#[derive(Deserialize)]
+pub struct ImportQuery {
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
+// Blocking reader over the chunks of a request body received from the
+// async side, so the CSV parser can consume the upload as it arrives
+struct ChannelReader {
+    receiver: Receiver<io::Result<Bytes>>,
+    chunk: Bytes,
+}
+
+impl Read for ChannelReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        while self.chunk.is_empty() {
+            match self.receiver.blocking_recv() {
+                Some(chunk) => self.chunk = chunk?,
+                None => return Ok(0),
+            }
+        }
+        let len = buf.len().min(self.chunk.len());
+        buf[..len].copy_from_slice(&self.chunk.split_to(len));
+        Ok(len)
+    }
+}
+
+// Import products from a CSV body. With `?dry_run=true` nothing is written.
+// Answers 422 with the per-row report when any row is invalid.
+#[post("/import")]
+pub async fn import(
+    user: RequireScope<WriteProducts>,
+    query: web::Query<ImportQuery>,
+    mut payload: web::Payload,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let actor = user.user;
+    let dry_run = query.dry_run;
+    let (sender, receiver) = channel(16);
+
+    // parse and write on a blocking thread while the body is still streaming in
+    let import = web::block(move || {
+        let conn = pg_pool_handler(pool)?;
+        let reader = ChannelReader {
+            receiver,
+            chunk: Bytes::new(),
+        };
+        product_csv::import(reader, ImportMode::Upsert, dry_run, &actor, &conn)
+            .map_err(ServerError::from)
+    });
+
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|err| io::Error::other(err.to_string()));
+        let failed = chunk.is_err();
+        // the import stopped reading, it will report why
+        if sender.send(chunk).await.is_err() || failed {
+            break;
+        }
+    }
+    drop(sender);
+
+    let report = import.await??;
+    if report.errors.is_empty() {
+        Ok(HttpResponse::Ok().json(report))
+    } else {
+        Ok(HttpResponse::UnprocessableEntity().json(report))
+    }
+}
+
+// Stream the catalog of the user's company as CSV
+#[get("/export")]
+pub async fn export(
+    user: RequireScope<ReadProducts>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let company = user.user.company;
+    // the state is the last exported id, `None` once everything was sent
+    let body = stream::unfold(Some(0), move |state| {
+        let pool = pool.clone();
+        let company = company.clone();
+        async move {
+            let after_id = state?;
+            let batch = run_blocking(pool, move |conn| -> Result<_, ServerError> {
+                let batch = export_batch(&company, after_id, conn)?;
+                let next = match batch.last() {
+                    Some(last) if batch.len() as i64 == EXPORT_BATCH_SIZE => Some(last.id),
+                    _ => None,
+                };
+                Ok((write_rows(&batch, after_id == 0)?, next))
+            })
+            .await;
+            match batch {
+                Ok((bytes, next)) => Some((Ok(Bytes::from(bytes)), next)),
+                Err(err) => Some((Err(actix_web::Error::from(err)), None)),
+            }
+        }
+    });
+
+    Ok(HttpResponse::Ok()
+        .content_type("text/csv")
+        .insert_header(ContentDisposition {
+            disposition: DispositionType::Attachment,
+            parameters: vec![DispositionParam::Filename("products.csv".to_string())],
+        })
+        .streaming(body))
+}
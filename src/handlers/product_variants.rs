@@ -0,0 +1,84 @@
+use actix_web::{delete, get, post, put, web, HttpResponse};
+
+use crate::db_connection::PgPool;
+use crate::errors::server_error::ServerError;
+use crate::handlers::{run_blocking, RequireScope};
+use crate::models::api_key::{ReadProducts, WriteProducts};
+use crate::models::product_variant::{NewVariant, ProductVariant};
+use crate::utils::validation::ValidatedJson;
+
+// List the variants of a product
+#[get("/{id}/variants")]
+pub async fn index(
+    user: RequireScope<ReadProducts>,
+    id: web::Path<i32>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let variants = run_blocking(pool, move |conn| {
+        ProductVariant::for_product(id, &user.company, conn)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(variants))
+}
+
+// Add a variant to a product
+#[post("/{id}/variants")]
+pub async fn create(
+    user: RequireScope<WriteProducts>,
+    id: web::Path<i32>,
+    new_variant: ValidatedJson<NewVariant>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let new_variant = new_variant.into_inner();
+    let variant = run_blocking(pool, move |conn| new_variant.create(id, &user, conn)).await?;
+    Ok(HttpResponse::Created().json(variant))
+}
+
+// Get a variant of a product
+#[get("/{id}/variants/{variant_id}")]
+pub async fn get(
+    user: RequireScope<ReadProducts>,
+    path: web::Path<(i32, i32)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let (id, variant_id) = path.into_inner();
+    let variant = run_blocking(pool, move |conn| {
+        ProductVariant::find(id, variant_id, &user.company, conn)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(variant))
+}
+
+// Update a variant of a product
+#[put("/{id}/variants/{variant_id}")]
+pub async fn update(
+    user: RequireScope<WriteProducts>,
+    path: web::Path<(i32, i32)>,
+    new_variant: ValidatedJson<NewVariant>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let (id, variant_id) = path.into_inner();
+    let new_variant = new_variant.into_inner();
+    let variant = run_blocking(pool, move |conn| {
+        new_variant.update(id, variant_id, &user, conn)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(variant))
+}
+
+// Delete a variant of a product
+#[delete("/{id}/variants/{variant_id}")]
+pub async fn destroy(
+    user: RequireScope<WriteProducts>,
+    path: web::Path<(i32, i32)>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let (id, variant_id) = path.into_inner();
+    run_blocking(pool, move |conn| {
+        ProductVariant::destroy(id, variant_id, &user, conn)
+    })
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
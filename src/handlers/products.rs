@@ -1,73 +1,79 @@
-use actix_web::{delete, get, post, put, web, HttpResponse};
+use actix_web::{delete, get, post, put, web, HttpRequest, HttpResponse};
 
 use crate::errors::server_error::ServerError;
-use crate::handlers::{pg_pool_handler, LoggedUser};
-use crate::models::product::{NewProduct, Product, ProductsList};
+use crate::handlers::{run_blocking, RequireScope};
+use crate::models::api_key::{ReadProducts, WriteProducts};
+use crate::models::product::{CreateProduct, NewProduct, Product, ProductQuery, ProductsList};
+use crate::utils::validation::ValidatedJson;
 
 use crate::db_connection::PgPool;
 
-// Add pool handlers
-
+// List products with pagination, filtering and sorting
 #[get("")]
 pub async fn index(
-    _user: LoggedUser,
+    user: RequireScope<ReadProducts>,
+    req: HttpRequest,
+    params: web::Query<ProductQuery>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ServerError> {
-    let pool = pg_pool_handler(pool)?;
-    Ok(HttpResponse::Ok().json(ProductsList::list(&pool)))
+    let params = params.into_inner();
+    let base_url = req.path().to_string();
+    let list = run_blocking(pool, move |conn| {
+        ProductsList::query(&params, &user.company, &base_url, conn)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(list))
 }
 
 // Create Product
 #[post("")]
 pub async fn create(
-    _user: LoggedUser,
-    new_product: web::Json<NewProduct>,
+    user: RequireScope<WriteProducts>,
+    new_product: ValidatedJson<CreateProduct>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ServerError> {
-    let pool = pg_pool_handler(pool)?;
-
-    new_product
-        .create(&pool)
-        .map(|product| HttpResponse::Created().json(product))
-        .map_err(|err| ServerError::InternalServerError(err.to_string()))
+    let new_product = NewProduct::from(new_product.into_inner());
+    let product = run_blocking(pool, move |conn| new_product.create(&user, conn)).await?;
+    Ok(HttpResponse::Created().json(product))
 }
 
-// Get a product by id
+// Get a product by id with its stock at each location
 #[get("/{id}")]
 pub async fn get(
-    _user: LoggedUser,
+    user: RequireScope<ReadProducts>,
     id: web::Path<i32>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ServerError> {
-    let pool = pg_pool_handler(pool)?;
-    Product::find(&id.into_inner(), &pool)
-        .map(|product| HttpResponse::Ok().json(product))
-        .map_err(|err| ServerError::InternalServerError(err.to_string()))
+    let id = id.into_inner();
+    let product = run_blocking(pool, move |conn| Product::detail(&id, &user.company, conn)).await?;
+    Ok(HttpResponse::Ok().json(product))
 }
 
 // Delete a product by id
 #[delete("/{id}")]
 pub async fn destroy(
-    _user: LoggedUser,
+    user: RequireScope<WriteProducts>,
     id: web::Path<i32>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ServerError> {
-    let pool = pg_pool_handler(pool)?;
-    Product::destroy(&id.into_inner(), &pool)
-        .map(|_| HttpResponse::NoContent().json(()))
-        .map_err(|err| ServerError::InternalServerError(err.to_string()))
+    let id = id.into_inner();
+    run_blocking(pool, move |conn| Product::destroy(&id, &user.company, conn)).await?;
+    Ok(HttpResponse::NoContent().json(()))
 }
 
 // Update a product by id
 #[put("/{id}")]
 async fn update(
-    _user: LoggedUser,
+    user: RequireScope<WriteProducts>,
     id: web::Path<i32>,
-    new_product: web::Json<NewProduct>,
+    new_product: ValidatedJson<NewProduct>,
     pool: web::Data<PgPool>,
 ) -> Result<HttpResponse, ServerError> {
-    let pool = pg_pool_handler(pool)?;
-    Product::update(&id.into_inner(), &new_product, &pool)
-        .map(|product| HttpResponse::Ok().json(product))
-        .map_err(|err| ServerError::InternalServerError(err.to_string()))
+    let id = id.into_inner();
+    let new_product = new_product.into_inner();
+    let product = run_blocking(pool, move |conn| {
+        Product::update(&id, &new_product, &user, conn)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(product))
 }
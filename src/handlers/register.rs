@@ -1,26 +1,205 @@
-use actix_web::{web, HttpResponse};
+use actix_web::{get, post, web, HttpRequest, HttpResponse};
+use chrono::Duration;
+use serde::Deserialize;
+use validator::Validate;
 
 use crate::{
+    config::Settings,
     db_connection::PgPool,
-    errors::server_error::ServerError,
+    errors::{application_error::ApplicationError, server_error::ServerError},
     models::user::{RegisterUser, User},
+    utils::{
+        jwt::{create_purpose_token, decode_purpose_token, VERIFY_EMAIL_PURPOSE},
+        login_throttle::{LoginThrottle, FAILURE_WINDOW, VERIFY_RESEND},
+        mailer::{send_blocking, Email, Mailer},
+        validation::ValidatedJson,
+    },
 };
 
-use super::pg_pool_handler;
+use super::authentication::client_ip;
+use super::run_blocking;
 
-pub fn register(
-    new_user: web::Json<RegisterUser>,
+// Verification links are valid for a day, a new one can be requested at
+// /auth/verify/resend
+const VERIFY_TOKEN_TTL_HOURS: i64 = 24;
+
+fn verification_email(user_email: &str, app_url: &str) -> Result<Email, ServerError> {
+    let token = create_purpose_token(
+        user_email,
+        VERIFY_EMAIL_PURPOSE,
+        Duration::hours(VERIFY_TOKEN_TTL_HOURS),
+    )?;
+    Ok(Email {
+        to: user_email.to_string(),
+        subject: "Verify your email address".to_string(),
+        body: format!(
+            "Open the following link to verify your email address:\n{}/auth/verify?token={}",
+            app_url, token
+        ),
+    })
+}
+
+#[post("/register")]
+pub async fn register(
+    new_user: ValidatedJson<RegisterUser>,
+    pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    settings: web::Data<Settings>,
+) -> Result<HttpResponse, ServerError> {
+    // create user, hashing the password on the blocking pool
+    let new_user = new_user.into_inner();
+    let user = run_blocking(pool, move |conn| User::create(&new_user, conn)).await?;
+
+    // send the verification link
+    let email = verification_email(&user.email, &settings.server.app_url)?;
+    if let Err(err) = send_blocking(mailer, email).await {
+        log::error!(
+            "Failed to send verification email to {}: {}",
+            user.email,
+            err
+        );
+    }
+
+    Ok(HttpResponse::Created().json(user))
+}
+
+#[derive(Deserialize)]
+pub struct VerifyQuery {
+    pub token: String,
+}
+
+#[get("/verify")]
+pub async fn verify(
+    query: web::Query<VerifyQuery>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let email = decode_purpose_token(&query.token, VERIFY_EMAIL_PURPOSE)?;
+    let user = run_blocking(pool, move |conn| {
+        User::verify_email(&email, conn).map_err(|err| match err {
+            ApplicationError::DBError(diesel::result::Error::NotFound) => {
+                ServerError::BadRequest("Invalid or expired token".to_string())
+            }
+            _ => err.into(),
+        })
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(user))
+}
+
+#[derive(Deserialize, Validate)]
+pub struct ResendVerification {
+    #[validate(email(message = "Email must be a valid email address"))]
+    pub email: String,
+}
+
+// Send a new verification link to an account that is not verified yet.
+// Always answers 202 before looking the email up, so neither the body nor
+// the response time tell whether it is registered. Throttled like logins.
+#[post("/verify/resend")]
+pub async fn resend(
+    req: HttpRequest,
+    resend_verification: ValidatedJson<ResendVerification>,
     pool: web::Data<PgPool>,
+    mailer: web::Data<dyn Mailer>,
+    throttle: web::Data<LoginThrottle>,
+    settings: web::Data<Settings>,
 ) -> Result<HttpResponse, ServerError> {
-    let pool = pg_pool_handler(pool)?;
-    // validate user password and password confirmation
-    let register_user = new_user
-        .into_inner()
-        .validate()
-        .map_err(|err| ServerError::InternalServerError(err.to_string()))?;
-
-    // create user
-    User::create(&register_user, &pool)
-        .map(|user| HttpResponse::Created().json(user))
-        .map_err(|err| ServerError::InternalServerError(err.to_string()))
+    let user_email = resend_verification.into_inner().email;
+    let ip = client_ip(&req, &settings.server.trusted_proxies);
+    if throttle
+        .counted(VERIFY_RESEND, &user_email, &ip)
+        .await
+        .is_locked()
+    {
+        return Err(ServerError::TooManyRequests(
+            "Too many verification emails requested, try again later".to_string(),
+            FAILURE_WINDOW.as_secs(),
+        ));
+    }
+    throttle.count(VERIFY_RESEND, &user_email, &ip).await;
+
+    actix_web::rt::spawn(async move {
+        let lookup = user_email.clone();
+        let user = match run_blocking(pool, move |conn| User::find_unverified(&lookup, conn)).await
+        {
+            Ok(Some(user)) => user,
+            Ok(None) => return,
+            Err(err) => {
+                log::error!("Failed to look up {} for verification: {}", user_email, err);
+                return;
+            }
+        };
+        let sent = match verification_email(&user.email, &settings.server.app_url) {
+            Ok(email) => send_blocking(mailer, email)
+                .await
+                .map_err(|err| err.to_string()),
+            Err(err) => Err(err.to_string()),
+        };
+        if let Err(err) = sent {
+            log::error!(
+                "Failed to send verification email to {}: {}",
+                user.email,
+                err
+            );
+        }
+    });
+    Ok(HttpResponse::Accepted().finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+    use std::time::Duration as StdDuration;
+
+    use actix_web::http::StatusCode;
+    use actix_web::{test, App};
+    use diesel::r2d2::{ConnectionManager, Pool};
+    use diesel::PgConnection;
+
+    use super::*;
+    use crate::utils::login_throttle::ACCOUNT_LOCKOUT_THRESHOLD;
+
+    #[derive(Default)]
+    struct RecordingMailer(Mutex<Vec<String>>);
+
+    impl Mailer for RecordingMailer {
+        fn send(&self, email: &Email) -> io::Result<()> {
+            self.0.lock().unwrap().push(email.to.clone());
+            Ok(())
+        }
+    }
+
+    #[actix_web::test]
+    async fn resend_always_accepts_until_throttled() {
+        // no database behind the pool, the lookup happens after answering
+        let manager = ConnectionManager::<PgConnection>::new("postgres://127.0.0.1:1/none");
+        let pool = Pool::builder()
+            .connection_timeout(StdDuration::from_millis(50))
+            .build_unchecked(manager);
+        let mailer: web::Data<dyn Mailer> =
+            web::Data::from(Arc::new(RecordingMailer::default()) as Arc<dyn Mailer>);
+        let app = test::init_service(
+            App::new()
+                .app_data(web::Data::new(pool))
+                .app_data(mailer)
+                .app_data(web::Data::new(LoginThrottle::in_memory()))
+                .app_data(web::Data::new(Settings::for_tests()))
+                .service(web::scope("/auth").service(resend)),
+        )
+        .await;
+        let request = || {
+            test::TestRequest::post()
+                .uri("/auth/verify/resend")
+                .set_json(serde_json::json!({"email": "nobody@x.io"}))
+                .to_request()
+        };
+
+        for _ in 0..ACCOUNT_LOCKOUT_THRESHOLD {
+            let res = test::call_service(&app, request()).await;
+            assert_eq!(res.status(), StatusCode::ACCEPTED);
+        }
+        let res = test::call_service(&app, request()).await;
+        assert_eq!(res.status(), StatusCode::TOO_MANY_REQUESTS);
+    }
 }
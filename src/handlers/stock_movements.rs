@@ -0,0 +1,51 @@
+use actix_web::{get, post, web, HttpResponse};
+
+use crate::db_connection::PgPool;
+use crate::errors::server_error::ServerError;
+use crate::handlers::{run_blocking, RequireScope};
+use crate::models::api_key::{ReadProducts, WriteProducts};
+use crate::models::stock_movement::{RecordMovement, StockMovement, StockTransfer};
+use crate::utils::validation::ValidatedJson;
+
+// List the stock movements of a product
+#[get("/{id}/movements")]
+pub async fn index(
+    user: RequireScope<ReadProducts>,
+    id: web::Path<i32>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let movements = run_blocking(pool, move |conn| {
+        StockMovement::for_product(id, &user.company, conn)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(movements))
+}
+
+// Record a stock movement for a product
+#[post("/{id}/movements")]
+pub async fn create(
+    user: RequireScope<WriteProducts>,
+    id: web::Path<i32>,
+    movement: ValidatedJson<RecordMovement>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let movement = movement.into_inner();
+    let movement = run_blocking(pool, move |conn| movement.record(id, &user, conn)).await?;
+    Ok(HttpResponse::Created().json(movement))
+}
+
+// Transfer stock of a product between two of its locations
+#[post("/{id}/transfers")]
+pub async fn transfer(
+    user: RequireScope<WriteProducts>,
+    id: web::Path<i32>,
+    transfer: ValidatedJson<StockTransfer>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let id = id.into_inner();
+    let transfer = transfer.into_inner();
+    let movements = run_blocking(pool, move |conn| transfer.execute(id, &user, conn)).await?;
+    Ok(HttpResponse::Created().json(movements))
+}
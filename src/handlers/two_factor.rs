@@ -0,0 +1,63 @@
+use actix_web::{delete, post, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    db_connection::PgPool,
+    errors::server_error::ServerError,
+    models::{two_factor::TwoFactorCode, user::User},
+};
+
+use super::{run_blocking, LoggedUser};
+
+// Code from the authenticator app that confirms the enrolment
+#[derive(Deserialize)]
+pub struct ConfirmTwoFactor {
+    pub code: String,
+}
+
+// Recovery codes are only ever shown once
+#[derive(Serialize)]
+pub struct RecoveryCodes {
+    pub recovery_codes: Vec<String>,
+}
+
+// Start enrolment, returns the secret and the uri to render as a QR code
+#[post("/2fa/enroll")]
+pub async fn enroll(
+    user: LoggedUser,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let enrollment =
+        run_blocking(pool, move |conn| User::enroll_two_factor(&user.email, conn)).await?;
+    Ok(HttpResponse::Ok().json(enrollment))
+}
+
+// Enable two-factor authentication with the first code from the app
+#[post("/2fa/confirm")]
+pub async fn confirm(
+    user: LoggedUser,
+    confirm: web::Json<ConfirmTwoFactor>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let code = confirm.into_inner().code;
+    let recovery_codes = run_blocking(pool, move |conn| {
+        User::confirm_two_factor(&user.email, &code, conn)
+    })
+    .await?;
+    Ok(HttpResponse::Ok().json(RecoveryCodes { recovery_codes }))
+}
+
+// Disable two-factor authentication, requires a code or a recovery code
+#[delete("/2fa")]
+pub async fn disable(
+    user: LoggedUser,
+    code: web::Json<TwoFactorCode>,
+    pool: web::Data<PgPool>,
+) -> Result<HttpResponse, ServerError> {
+    let code = code.into_inner();
+    run_blocking(pool, move |conn| {
+        User::disable_two_factor(&user.email, &code, conn)
+    })
+    .await?;
+    Ok(HttpResponse::NoContent().finish())
+}
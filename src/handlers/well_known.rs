@@ -0,0 +1,15 @@
+use actix_web::http::header::{CacheControl, CacheDirective};
+use actix_web::{get, HttpResponse};
+
+use crate::utils::keys::key_store;
+
+// Public keys other services use to verify our tokens
+#[get("/.well-known/jwks.json")]
+pub async fn jwks() -> HttpResponse {
+    HttpResponse::Ok()
+        .insert_header(CacheControl(vec![
+            CacheDirective::Public,
+            CacheDirective::MaxAge(300),
+        ]))
+        .json(key_store().jwks())
+}
@@ -0,0 +1,18 @@
+// diesel 1.4's derives generate impls inside anonymous consts
+#![allow(non_local_definitions)]
+
+extern crate serde;
+extern crate serde_json;
+
+#[macro_use]
+extern crate diesel;
+extern crate dotenv;
+
+pub mod config;
+pub mod db_connection;
+pub mod db_migrations;
+pub mod errors;
+pub mod handlers;
+pub mod models;
+pub mod schema;
+pub mod utils;
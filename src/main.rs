@@ -1,10 +1,3 @@
-extern crate serde;
-extern crate serde_json;
-
-#[macro_use]
-extern crate diesel;
-extern crate dotenv;
-
 use actix_cors::Cors;
 use actix_identity::IdentityMiddleware;
 use actix_session::storage::RedisSessionStore;
@@ -16,85 +9,201 @@ use actix_web::{
     web::{self, Data},
     App, HttpRequest, HttpResponse, HttpServer, Responder,
 };
-use csrf::AesGcmCsrfProtection;
-use db_connection::establish_connection;
+use rust_store_v2::config::{decode_key, Settings};
+use rust_store_v2::db_connection::establish_connection;
+use rust_store_v2::errors::server_error::ServerError;
+use rust_store_v2::{db_migrations, handlers};
 
-use std::sync::Mutex;
-pub mod db_connection;
-pub mod errors;
-pub mod handlers;
-pub mod models;
-pub mod schema;
-pub mod utils;
+use rust_store_v2::utils::csrf::{Csrf, CSRF_COOKIE_HEADER, CSRF_TOKEN_HEADER};
+use rust_store_v2::utils::keys::{self, KeyStore};
+use rust_store_v2::utils::login_throttle::LoginThrottle;
+use rust_store_v2::utils::mailer::{FileMailer, Mailer};
+use rust_store_v2::utils::metrics::RequestMetrics;
+use std::process;
+use std::sync::Arc;
 
 async fn index(_req: HttpRequest) -> impl Responder {
     HttpResponse::Ok().body("Hello world!")
 }
 
+// Stop before serving anything when the configuration is unusable
+fn exit_with(message: String) -> ! {
+    eprintln!("{}", message);
+    process::exit(1)
+}
+
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
-    std::env::set_var("RUST_LOG", "debug");
-    std::env::set_var("RUST_BACKTRACE", "1");
-    // init env_logger
-    env_logger::init();
-    let secret_key = Key::generate();
-    let redis_store = RedisSessionStore::new("redis://127.0.0.1:6379")
-        .await
-        .unwrap();
-    let csrf_token_header = header::HeaderName::from_lowercase(b"x-csrf-token").unwrap();
-    let csrf_token_cookie_header =
-        header::HeaderName::from_lowercase(b"x-csrf-token-cookie").unwrap();
+    // `migrate <list|run|revert>` manages the schema instead of serving, it
+    // only needs the database settings
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(String::as_str) {
+        Some("migrate") => {
+            let database = Settings::load_database().unwrap_or_else(|err| exit_with(err));
+            let command = args.get(1).map(String::as_str);
+            db_migrations::migrate_command(&database, command).unwrap_or_else(|err| exit_with(err));
+            return Ok(());
+        }
+        Some(_) => exit_with(db_migrations::MIGRATE_USAGE.to_string()),
+        None => {}
+    }
 
-    let generator = AesGcmCsrfProtection::from_key(*b"01234567012345670123456701234567");
-    let wrapped_generator = web::Data::new(Mutex::new(generator));
+    let settings = Settings::load().unwrap_or_else(|err| exit_with(err));
+    // init env_logger, RUST_LOG overrides the configured level
+    env_logger::Builder::from_env(
+        env_logger::Env::default().default_filter_or(&settings.server.log_level),
+    )
+    .init();
+    if settings.database.run_migrations {
+        db_migrations::migrate_on_startup(&settings.database).unwrap_or_else(|err| exit_with(err));
+    }
 
-    let pool = Data::new(establish_connection());
+    // a configured key keeps sessions valid across restarts
+    let secret_key = decode_key(&settings.session.key, 64)
+        .map(|key| Key::from(&key))
+        .unwrap_or_else(|err| exit_with(format!("session.key {}", err)));
+    let redis_store = RedisSessionStore::new(settings.redis.url.as_str())
+        .await
+        .unwrap_or_else(|err| exit_with(format!("Cannot connect to Redis: {}", err)));
+    let login_throttle = Data::new(LoginThrottle::connect(&settings.redis.url).await);
+    let redis_client = Data::new(
+        redis::Client::open(settings.redis.url.as_str())
+            .unwrap_or_else(|err| exit_with(format!("Invalid redis.url: {}", err))),
+    );
+    let csrf = Csrf::from_settings(&settings.csrf).unwrap_or_else(|err| exit_with(err));
+
+    let pool = Data::new(establish_connection(&settings.database));
+    // token signing keys are loaded once, a missing or invalid key stops startup
+    keys::init(KeyStore::from_settings(&settings.jwt).unwrap_or_else(|err| exit_with(err)));
+    let mailer: Data<dyn Mailer> =
+        Data::from(Arc::new(FileMailer::new(settings.mail.outbox_dir.clone())) as Arc<dyn Mailer>);
+    let bind_address = (settings.server.host.clone(), settings.server.port);
+    let workers = settings.server.workers;
+    let max_blocking_threads = settings.server.max_blocking_threads;
+    let settings = Data::new(settings);
     // Create an instance of the server.
-    HttpServer::new(move || {
-        let cors = Cors::default()
-            .allowed_origin("redis://127.0.0.")
+    let server = HttpServer::new(move || {
+        let cors = settings
+            .cors
+            .allowed_origins
+            .iter()
+            .fold(Cors::default(), |cors, origin| cors.allowed_origin(origin))
             .allowed_methods(vec!["GET", "POST", "PUT", "DELETE"])
             .allowed_headers(vec![
                 header::AUTHORIZATION,
                 header::CONTENT_TYPE,
                 header::ACCEPT,
-                csrf_token_header.clone(),
-                csrf_token_cookie_header.clone(),
-            ])
-            .expose_headers(vec![
-                csrf_token_header.clone(),
-                csrf_token_cookie_header.clone(),
+                CSRF_TOKEN_HEADER,
+                CSRF_COOKIE_HEADER,
             ])
-            .max_age(3600);
+            .expose_headers(vec![CSRF_TOKEN_HEADER, CSRF_COOKIE_HEADER])
+            .max_age(settings.cors.max_age_seconds);
         // Create an instance of the app.
         App::new()
             .wrap(Logger::default())
             .wrap(IdentityMiddleware::default())
-            .wrap(SessionMiddleware::new(
-                redis_store.clone(),
-                secret_key.clone(),
-            ))
+            .wrap(
+                SessionMiddleware::builder(redis_store.clone(), secret_key.clone())
+                    .cookie_secure(settings.session.cookie_secure)
+                    .build(),
+            )
+            .wrap(csrf.clone())
             .wrap(cors)
-            .app_data(Data::clone(&wrapped_generator))
+            .wrap(RequestMetrics)
+            .app_data(settings.clone())
             .app_data(pool.clone())
+            .app_data(mailer.clone())
+            .app_data(login_throttle.clone())
+            .app_data(redis_client.clone())
+            // extractor errors use the same problem+json body as handlers
+            .app_data(
+                web::JsonConfig::default()
+                    .error_handler(|err, _| ServerError::BadRequest(err.to_string()).into()),
+            )
+            .app_data(
+                web::QueryConfig::default()
+                    .error_handler(|err, _| ServerError::BadRequest(err.to_string()).into()),
+            )
+            .app_data(
+                web::PathConfig::default()
+                    .error_handler(|err, _| ServerError::NotFound(err.to_string()).into()),
+            )
             .route("/", web::get().to(index))
+            .service(handlers::well_known::jwks)
+            .service(handlers::metrics::index)
+            .service(
+                web::scope("/health")
+                    .service(handlers::health::live)
+                    .service(handlers::health::ready),
+            )
             // Route the index function to the root path.
             .service(
                 web::scope("/products")
                     .service(handlers::products::index)
+                    // registered before `/{id}` so they are not taken for an id
+                    .service(handlers::product_csv::import)
+                    .service(handlers::product_csv::export)
                     .service(handlers::products::get)
                     .service(handlers::products::update)
                     .service(handlers::products::create)
-                    .service(handlers::products::destroy),
+                    .service(handlers::products::destroy)
+                    .service(handlers::stock_movements::index)
+                    .service(handlers::stock_movements::create)
+                    .service(handlers::stock_movements::transfer)
+                    .service(handlers::categories::for_product)
+                    .service(handlers::categories::assign)
+                    .service(handlers::product_variants::index)
+                    .service(handlers::product_variants::create)
+                    .service(handlers::product_variants::get)
+                    .service(handlers::product_variants::update)
+                    .service(handlers::product_variants::destroy),
+            )
+            .service(
+                web::scope("/categories")
+                    .service(handlers::categories::index)
+                    .service(handlers::categories::create)
+                    .service(handlers::categories::get)
+                    .service(handlers::categories::update)
+                    .service(handlers::categories::destroy),
+            )
+            .service(
+                web::scope("/locations")
+                    .service(handlers::locations::index)
+                    .service(handlers::locations::create)
+                    .service(handlers::locations::get)
+                    .service(handlers::locations::update)
+                    .service(handlers::locations::destroy),
+            )
+            .service(
+                web::scope("/api-keys")
+                    .service(handlers::api_keys::index)
+                    .service(handlers::api_keys::create)
+                    .service(handlers::api_keys::revoke),
             )
             .service(
                 web::scope("/auth")
                     .service(handlers::authentication::login)
-                    .service(handlers::authentication::logout),
+                    .service(handlers::authentication::login_two_factor)
+                    .service(handlers::authentication::logout)
+                    .service(handlers::authentication::refresh)
+                    .service(handlers::register::register)
+                    .service(handlers::register::verify)
+                    .service(handlers::register::resend)
+                    .service(handlers::password::forgot)
+                    .service(handlers::password::reset)
+                    .service(handlers::two_factor::enroll)
+                    .service(handlers::two_factor::confirm)
+                    .service(handlers::two_factor::disable),
             )
-    })
-    // bind to port 8080 and run the server on "127.0.0.1"
-    .bind(("127.0.0.1", 8088))?
-    .run()
-    .await
+    });
+    let server = match workers {
+        Some(workers) => server.workers(workers),
+        None => server,
+    };
+    // database queries and password hashing run on these threads
+    let server = match max_blocking_threads {
+        Some(threads) => server.worker_max_blocking_threads(threads),
+        None => server,
+    };
+    server.bind(bind_address)?.run().await
 }
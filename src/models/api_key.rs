@@ -0,0 +1,291 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::diesel::ExpressionMethods;
+use crate::errors::application_error::ApplicationError;
+use crate::models::role::Role;
+use crate::schema::{api_keys, users};
+use crate::utils::jwt::SlimUser;
+use crate::utils::token::{generate_token, hash_token};
+use chrono::{Duration, Local, NaiveDateTime};
+use data_encoding::HEXLOWER;
+use diesel::OptionalExtension;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+// Every key starts with this marker so leaked keys are easy to recognise
+pub const API_KEY_MARKER: &str = "rs";
+// Keys are refused after this many days unless an earlier expiry is given
+pub const MAX_API_KEY_TTL_DAYS: i64 = 365;
+// last_used_at is written at most once per interval, not on every request
+const LAST_USED_RESOLUTION_SECONDS: i64 = 60;
+
+// Permission granted to an API key
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ApiScope {
+    #[serde(rename = "products:read")]
+    ProductsRead,
+    #[serde(rename = "products:write")]
+    ProductsWrite,
+}
+
+impl ApiScope {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiScope::ProductsRead => "products:read",
+            ApiScope::ProductsWrite => "products:write",
+        }
+    }
+
+    // Whether a key with this scope may use endpoints requiring `scope`,
+    // writing products includes reading them
+    pub fn grants(&self, scope: ApiScope) -> bool {
+        *self == scope || (*self, scope) == (ApiScope::ProductsWrite, ApiScope::ProductsRead)
+    }
+}
+
+// Type-level scopes used with the `RequireScope` extractor, along with the
+// role a user session needs for the same endpoints
+pub trait RequiredScope {
+    const SCOPE: ApiScope;
+    const ROLE: Role;
+}
+
+pub struct ReadProducts;
+pub struct WriteProducts;
+
+impl RequiredScope for ReadProducts {
+    const SCOPE: ApiScope = ApiScope::ProductsRead;
+    const ROLE: Role = Role::Viewer;
+}
+
+impl RequiredScope for WriteProducts {
+    const SCOPE: ApiScope = ApiScope::ProductsWrite;
+    const ROLE: Role = Role::Editor;
+}
+
+impl fmt::Display for ApiScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for ApiScope {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<ApiScope, String> {
+        match value {
+            "products:read" => Ok(ApiScope::ProductsRead),
+            "products:write" => Ok(ApiScope::ProductsWrite),
+            _ => Err(format!("Unknown scope {}", value)),
+        }
+    }
+}
+
+// Create a struct to represent an api key.
+#[derive(Queryable, Serialize, Debug)]
+pub struct ApiKey {
+    pub id: i32,
+    #[serde(skip)]
+    pub company: String,
+    pub name: String,
+    pub prefix: String,
+    #[serde(skip)]
+    pub key_hash: String,
+    pub scopes: Vec<String>,
+    pub created_by: String,
+    pub expires_at: Option<NaiveDateTime>,
+    pub last_used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+// Struct for inserting a new api key into database
+#[derive(Insertable, Debug)]
+#[table_name = "api_keys"]
+struct NewApiKey {
+    company: String,
+    name: String,
+    prefix: String,
+    key_hash: String,
+    scopes: Vec<String>,
+    created_by: String,
+    expires_at: Option<NaiveDateTime>,
+    created_at: NaiveDateTime,
+}
+
+fn validate_scopes(scopes: &[ApiScope]) -> Result<(), ValidationError> {
+    if scopes.is_empty() {
+        let mut error = ValidationError::new("scopes");
+        error.message = Some("At least one scope is required".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+// Create api key model
+#[derive(Deserialize, Validate)]
+pub struct CreateApiKey {
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "Name must be between 1 and 100 characters long"
+    ))]
+    pub name: String,
+    #[validate(custom = "validate_scopes")]
+    pub scopes: Vec<ApiScope>,
+    #[validate(range(min = 1, max = 365, message = "Expiry must be between 1 and 365 days"))]
+    pub expires_in_days: Option<i64>,
+}
+
+// A newly created key, the only time the plain key is returned
+#[derive(Serialize)]
+pub struct CreatedApiKey {
+    pub key: String,
+    #[serde(flatten)]
+    pub api_key: ApiKey,
+}
+
+fn invalid_key() -> ApplicationError {
+    ApplicationError::InvalidToken("Invalid or expired API key".to_string())
+}
+
+impl CreateApiKey {
+    // Create a key for the company of the actor
+    pub fn create(
+        &self,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<CreatedApiKey, ApplicationError> {
+        let mut prefix = [0u8; 6];
+        rand::thread_rng().fill_bytes(&mut prefix);
+        let prefix = HEXLOWER.encode(&prefix);
+        let key = format!("{}_{}_{}", API_KEY_MARKER, prefix, generate_token());
+
+        let mut scopes = self
+            .scopes
+            .iter()
+            .map(|scope| scope.to_string())
+            .collect::<Vec<_>>();
+        scopes.sort();
+        scopes.dedup();
+
+        let now = Local::now().naive_local();
+        let api_key = diesel::insert_into(api_keys::table)
+            .values(&NewApiKey {
+                company: actor.company.to_string(),
+                name: self.name.to_string(),
+                prefix,
+                key_hash: hash_token(&key),
+                scopes,
+                created_by: actor.email.to_string(),
+                expires_at: Some(
+                    now + Duration::days(self.expires_in_days.unwrap_or(MAX_API_KEY_TTL_DAYS)),
+                ),
+                created_at: now,
+            })
+            .get_result::<ApiKey>(conn)?;
+        Ok(CreatedApiKey { key, api_key })
+    }
+}
+
+impl ApiKey {
+    pub fn api_scopes(&self) -> Vec<ApiScope> {
+        self.scopes
+            .iter()
+            .filter_map(|scope| scope.parse::<ApiScope>().ok())
+            .collect()
+    }
+
+    pub fn list(owner: &str, conn: &PgConnection) -> Result<Vec<ApiKey>, ApplicationError> {
+        Ok(api_keys::table
+            .filter(api_keys::company.eq(owner))
+            .order(api_keys::id.asc())
+            .load::<ApiKey>(conn)?)
+    }
+
+    pub fn revoke(id: i32, owner: &str, conn: &PgConnection) -> Result<ApiKey, ApplicationError> {
+        Ok(diesel::update(
+            api_keys::table
+                .filter(api_keys::id.eq(id))
+                .filter(api_keys::company.eq(owner)),
+        )
+        .set(api_keys::revoked_at.eq(Some(Local::now().naive_local())))
+        .get_result::<ApiKey>(conn)?)
+    }
+
+    // Find the active key for a plain key presented by a client and record
+    // that it was used. Keys stop working while their creator is disabled.
+    pub fn authenticate(key: &str, conn: &PgConnection) -> Result<ApiKey, ApplicationError> {
+        let mut parts = key.splitn(3, '_');
+        let prefix = match (parts.next(), parts.next(), parts.next()) {
+            (Some(API_KEY_MARKER), Some(prefix), Some(_)) => prefix,
+            _ => return Err(invalid_key()),
+        };
+
+        let now = Local::now().naive_local();
+        let api_key = api_keys::table
+            .filter(api_keys::prefix.eq(prefix))
+            .filter(api_keys::revoked_at.is_null())
+            .filter(diesel::dsl::exists(
+                users::table
+                    .filter(users::email.eq(api_keys::created_by))
+                    .filter(users::disabled_at.is_null()),
+            ))
+            .first::<ApiKey>(conn)
+            .optional()?
+            .ok_or_else(invalid_key)?;
+        let hash_matches = ring::constant_time::verify_slices_are_equal(
+            api_key.key_hash.as_bytes(),
+            hash_token(key).as_bytes(),
+        )
+        .is_ok();
+        if !hash_matches || api_key.expires_at.is_some_and(|expiry| expiry <= now) {
+            return Err(invalid_key());
+        }
+
+        let stale = now - Duration::seconds(LAST_USED_RESOLUTION_SECONDS);
+        if api_key.last_used_at.is_none_or(|used| used < stale) {
+            diesel::update(api_keys::table.find(api_key.id))
+                .set(api_keys::last_used_at.eq(Some(now)))
+                .execute(conn)?;
+        }
+        Ok(api_key)
+    }
+}
+
+impl From<ApiKey> for SlimUser {
+    fn from(api_key: ApiKey) -> SlimUser {
+        SlimUser {
+            // keys act through their scopes, never through a role
+            role: Role::Viewer,
+            api_scopes: api_key.api_scopes(),
+            email: api_key.created_by,
+            company: api_key.company,
+            issued_at: api_key.created_at.timestamp() as usize,
+            expires_at: api_key
+                .expires_at
+                .map(|expiry| expiry.timestamp() as usize)
+                .unwrap_or(usize::MAX),
+            token_id: api_key.prefix,
+            api_key_id: Some(api_key.id),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writing_products_includes_reading_them() {
+        assert!(ApiScope::ProductsWrite.grants(ApiScope::ProductsRead));
+        assert!(ApiScope::ProductsWrite.grants(ApiScope::ProductsWrite));
+        assert!(ApiScope::ProductsRead.grants(ApiScope::ProductsRead));
+        assert!(!ApiScope::ProductsRead.grants(ApiScope::ProductsWrite));
+    }
+}
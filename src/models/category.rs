@@ -0,0 +1,322 @@
+use std::collections::BTreeSet;
+
+use crate::diesel::ExpressionMethods;
+use crate::errors::application_error::ApplicationError;
+use crate::models::product::Product;
+use crate::schema::{categories, product_categories};
+use crate::utils::jwt::SlimUser;
+use chrono::{Local, NaiveDateTime};
+use diesel::result::DatabaseErrorKind;
+use diesel::sql_types::{Integer, Text};
+use diesel::Connection;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+// Create a struct to represent a category. `path` lists the ids from the root
+// down to the category itself, e.g. `/1/4/9/`.
+#[derive(Queryable, Serialize, Debug)]
+pub struct Category {
+    pub id: i32,
+    #[serde(skip)]
+    pub company: String,
+    pub parent_id: Option<i32>,
+    pub name: String,
+    pub path: String,
+    pub created_at: NaiveDateTime,
+}
+
+// Struct for inserting a new category into database, the path is set once
+// the id is known
+#[derive(Insertable, Debug)]
+#[table_name = "categories"]
+struct InsertCategory<'a> {
+    company: &'a str,
+    parent_id: Option<i32>,
+    name: &'a str,
+    path: &'a str,
+    created_at: NaiveDateTime,
+}
+
+fn sibling_conflict(err: diesel::result::Error) -> ApplicationError {
+    match err {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+            ApplicationError::CategoryConflict(
+                "A category with this name already exists here".to_string(),
+            )
+        }
+        _ => ApplicationError::DBError(err),
+    }
+}
+
+// Changes to the tree of one company run one at a time. Two concurrent moves
+// could otherwise each pass the cycle check and form a loop, and a child
+// created while an ancestor moves would keep the old path prefix.
+fn lock_tree(owner: &str, conn: &PgConnection) -> Result<(), diesel::result::Error> {
+    diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+        .bind::<Text, _>(format!("categories:{}", owner))
+        .execute(conn)?;
+    Ok(())
+}
+
+// Path of a category below the given parent path, or below the root
+fn path_below(parent_path: Option<&str>, id: i32) -> String {
+    format!("{}{}/", parent_path.unwrap_or("/"), id)
+}
+
+// Whether a path is the category at `path` itself or one of its descendants
+fn is_within(candidate: &str, path: &str) -> bool {
+    candidate.starts_with(path)
+}
+
+// Every category lookup is scoped to the company that owns it, like products
+impl Category {
+    pub fn list(owner: &str, conn: &PgConnection) -> Result<Vec<Category>, ApplicationError> {
+        // ordering by path lists every parent right before its subtree
+        Ok(categories::table
+            .filter(categories::company.eq(owner))
+            .order(categories::path.asc())
+            .load::<Category>(conn)?)
+    }
+
+    pub fn find(
+        search_id: i32,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<Category, diesel::result::Error> {
+        categories::table
+            .find(search_id)
+            .filter(categories::company.eq(owner))
+            .first(conn)
+    }
+
+    // The parent a category is created or moved under, a missing one is a
+    // client error rather than a 404 of the category itself
+    fn find_parent(
+        parent_id: Option<i32>,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<Option<Category>, ApplicationError> {
+        parent_id
+            .map(|parent_id| {
+                Category::find(parent_id, owner, conn).map_err(|err| match err {
+                    diesel::result::Error::NotFound => ApplicationError::InvalidCategory(format!(
+                        "Parent category {} not found",
+                        parent_id
+                    )),
+                    _ => ApplicationError::DBError(err),
+                })
+            })
+            .transpose()
+    }
+
+    // Delete a category without subcategories, its products stay in place
+    pub fn destroy(
+        search_id: i32,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<(), ApplicationError> {
+        conn.transaction(|| {
+            let category = Category::find(search_id, owner, conn)?;
+            let has_children = diesel::select(diesel::dsl::exists(
+                categories::table.filter(categories::parent_id.eq(category.id)),
+            ))
+            .get_result::<bool>(conn)?;
+            if has_children {
+                return Err(ApplicationError::CategoryConflict(
+                    "Move or delete the subcategories first".to_string(),
+                ));
+            }
+            diesel::delete(categories::table.find(category.id)).execute(conn)?;
+            Ok(())
+        })
+    }
+
+    // Categories a product is assigned to
+    pub fn for_product(
+        product_id: i32,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<Vec<Category>, ApplicationError> {
+        Product::find(&product_id, owner, conn)?;
+        Ok(categories::table
+            .inner_join(product_categories::table)
+            .filter(product_categories::product_id.eq(product_id))
+            .select(categories::all_columns)
+            .order(categories::path.asc())
+            .load::<Category>(conn)?)
+    }
+
+    // Replace the categories of a product
+    pub fn assign(
+        product_id: i32,
+        assignment: &CategoryAssignment,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<Vec<Category>, ApplicationError> {
+        let category_ids: BTreeSet<i32> = assignment.category_ids.iter().copied().collect();
+        conn.transaction(|| {
+            Product::find(&product_id, &actor.company, conn)?;
+            let known = categories::table
+                .filter(categories::company.eq(&actor.company))
+                .filter(categories::id.eq_any(category_ids.iter().copied().collect::<Vec<_>>()))
+                .select(categories::id)
+                .load::<i32>(conn)?;
+            if let Some(unknown) = category_ids.iter().find(|id| !known.contains(id)) {
+                return Err(ApplicationError::InvalidCategory(format!(
+                    "Category {} not found",
+                    unknown
+                )));
+            }
+
+            diesel::delete(
+                product_categories::table.filter(product_categories::product_id.eq(product_id)),
+            )
+            .execute(conn)?;
+            let rows: Vec<_> = category_ids
+                .iter()
+                .map(|category_id| {
+                    (
+                        product_categories::product_id.eq(product_id),
+                        product_categories::category_id.eq(*category_id),
+                    )
+                })
+                .collect();
+            if !rows.is_empty() {
+                diesel::insert_into(product_categories::table)
+                    .values(&rows)
+                    .execute(conn)?;
+            }
+            Category::for_product(product_id, &actor.company, conn)
+        })
+    }
+
+    // Path prefix matching the category and all of its descendants
+    pub fn subtree_pattern(
+        search_id: i32,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<String, ApplicationError> {
+        let category = Category::find(search_id, owner, conn).map_err(|err| match err {
+            diesel::result::Error::NotFound => {
+                ApplicationError::InvalidQuery(format!("Category {} not found", search_id))
+            }
+            _ => ApplicationError::DBError(err),
+        })?;
+        // paths only hold digits and slashes, nothing to escape
+        Ok(format!("{}%", category.path))
+    }
+}
+
+// Create or update category model, a category without parent is a root
+#[derive(Deserialize, Validate)]
+pub struct NewCategory {
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "Name must be between 1 and 100 characters long"
+    ))]
+    pub name: String,
+    pub parent_id: Option<i32>,
+}
+
+impl NewCategory {
+    pub fn create(
+        &self,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<Category, ApplicationError> {
+        conn.transaction(|| {
+            lock_tree(&actor.company, conn)?;
+            let parent = Category::find_parent(self.parent_id, &actor.company, conn)?;
+            let category = diesel::insert_into(categories::table)
+                .values(&InsertCategory {
+                    company: &actor.company,
+                    parent_id: self.parent_id,
+                    name: &self.name,
+                    path: "",
+                    created_at: Local::now().naive_local(),
+                })
+                .get_result::<Category>(conn)
+                .map_err(sibling_conflict)?;
+            let parent_path = parent.as_ref().map(|parent| parent.path.as_str());
+            Ok(diesel::update(categories::table.find(category.id))
+                .set(categories::path.eq(path_below(parent_path, category.id)))
+                .get_result::<Category>(conn)?)
+        })
+    }
+
+    // Rename the category and move it, with its whole subtree, under a new parent
+    pub fn update(
+        &self,
+        search_id: i32,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<Category, ApplicationError> {
+        conn.transaction(|| {
+            lock_tree(&actor.company, conn)?;
+            let current = categories::table
+                .find(search_id)
+                .filter(categories::company.eq(&actor.company))
+                .for_update()
+                .first::<Category>(conn)?;
+
+            if self.parent_id != current.parent_id {
+                let parent = Category::find_parent(self.parent_id, &actor.company, conn)?;
+                let parent_path = parent.as_ref().map(|parent| parent.path.as_str());
+                if parent_path.is_some_and(|parent_path| is_within(parent_path, &current.path)) {
+                    return Err(ApplicationError::InvalidCategory(
+                        "A category cannot be moved below itself".to_string(),
+                    ));
+                }
+                let new_path = path_below(parent_path, current.id);
+                // rewrite the prefix of the category and every descendant
+                diesel::sql_query(
+                    "UPDATE categories SET path = $1 || substr(path, $2) \
+                     WHERE company = $3 AND path LIKE $4",
+                )
+                .bind::<Text, _>(&new_path)
+                .bind::<Integer, _>(current.path.len() as i32 + 1)
+                .bind::<Text, _>(&actor.company)
+                .bind::<Text, _>(format!("{}%", current.path))
+                .execute(conn)?;
+            }
+
+            diesel::update(categories::table.find(current.id))
+                .set((
+                    categories::name.eq(&self.name),
+                    categories::parent_id.eq(self.parent_id),
+                ))
+                .get_result::<Category>(conn)
+                .map_err(sibling_conflict)
+        })
+    }
+}
+
+// Categories to assign to a product, replacing the current ones
+#[derive(Deserialize)]
+pub struct CategoryAssignment {
+    pub category_ids: Vec<i32>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_paths_from_the_root_down() {
+        assert_eq!(path_below(None, 4), "/4/");
+        assert_eq!(path_below(Some("/1/4/"), 9), "/1/4/9/");
+    }
+
+    #[test]
+    fn finds_the_category_and_its_descendants() {
+        assert!(is_within("/1/4/", "/1/4/"));
+        assert!(is_within("/1/4/9/", "/1/4/"));
+        assert!(!is_within("/1/", "/1/4/"));
+        // the closing slash keeps category 4 apart from category 40
+        assert!(!is_within("/1/40/", "/1/4/"));
+    }
+}
@@ -0,0 +1,162 @@
+use crate::diesel::ExpressionMethods;
+use crate::errors::application_error::ApplicationError;
+use crate::schema::{locations, stock_levels};
+use crate::utils::jwt::SlimUser;
+use chrono::{Local, NaiveDateTime};
+use diesel::result::DatabaseErrorKind;
+use diesel::Connection;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+// Create a struct to represent a location stock is held at, e.g. a warehouse
+#[derive(Queryable, Serialize, Debug)]
+pub struct Location {
+    pub id: i32,
+    #[serde(skip)]
+    pub company: String,
+    pub name: String,
+    pub created_at: NaiveDateTime,
+}
+
+// Stock of a product held at one location, summed over its variants
+#[derive(Serialize, Debug)]
+pub struct LocationStock {
+    pub location_id: i32,
+    pub name: String,
+    pub stock: f64,
+}
+
+fn name_conflict(err: diesel::result::Error) -> ApplicationError {
+    match err {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+            ApplicationError::LocationConflict(
+                "A location with this name already exists".to_string(),
+            )
+        }
+        _ => ApplicationError::DBError(err),
+    }
+}
+
+// Every location lookup is scoped to the company that owns it, like products
+impl Location {
+    pub fn list(owner: &str, conn: &PgConnection) -> Result<Vec<Location>, ApplicationError> {
+        Ok(locations::table
+            .filter(locations::company.eq(owner))
+            .order(locations::name.asc())
+            .load::<Location>(conn)?)
+    }
+
+    pub fn find(
+        search_id: i32,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<Location, diesel::result::Error> {
+        locations::table
+            .find(search_id)
+            .filter(locations::company.eq(owner))
+            .first(conn)
+    }
+
+    // Delete a location that holds no stock
+    pub fn destroy(
+        search_id: i32,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<(), ApplicationError> {
+        conn.transaction(|| {
+            let location = locations::table
+                .find(search_id)
+                .filter(locations::company.eq(owner))
+                .for_update()
+                .first::<Location>(conn)?;
+            let holds_stock = diesel::select(diesel::dsl::exists(
+                stock_levels::table
+                    .filter(stock_levels::location_id.eq(location.id))
+                    .filter(stock_levels::quantity.gt(0.0)),
+            ))
+            .get_result::<bool>(conn)?;
+            if holds_stock {
+                return Err(ApplicationError::LocationConflict(
+                    "Transfer the stock held at this location first".to_string(),
+                ));
+            }
+            diesel::delete(locations::table.find(location.id)).execute(conn)?;
+            Ok(())
+        })
+    }
+
+    // Stock of a product at each location holding some, by location name
+    pub fn stock_of_product(
+        product_id: i32,
+        conn: &PgConnection,
+    ) -> Result<Vec<LocationStock>, ApplicationError> {
+        let levels = stock_levels::table
+            .inner_join(locations::table)
+            .filter(stock_levels::product_id.eq(product_id))
+            .filter(stock_levels::quantity.gt(0.0))
+            .order((locations::name.asc(), locations::id.asc()))
+            .select((locations::id, locations::name, stock_levels::quantity))
+            .load::<(i32, String, f64)>(conn)?;
+        // one row per variant, consecutive for a location thanks to the ordering
+        let mut by_location: Vec<LocationStock> = Vec::new();
+        for (location_id, name, quantity) in levels {
+            match by_location.last_mut() {
+                Some(last) if last.location_id == location_id => last.stock += quantity,
+                _ => by_location.push(LocationStock {
+                    location_id,
+                    name,
+                    stock: quantity,
+                }),
+            }
+        }
+        Ok(by_location)
+    }
+}
+
+// Create or update location model
+#[derive(Deserialize, Validate)]
+pub struct NewLocation {
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "Name must be between 1 and 100 characters long"
+    ))]
+    pub name: String,
+}
+
+impl NewLocation {
+    pub fn create(
+        &self,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<Location, ApplicationError> {
+        diesel::insert_into(locations::table)
+            .values((
+                locations::company.eq(&actor.company),
+                locations::name.eq(&self.name),
+                locations::created_at.eq(Local::now().naive_local()),
+            ))
+            .get_result::<Location>(conn)
+            .map_err(name_conflict)
+    }
+
+    // Rename a location
+    pub fn update(
+        &self,
+        search_id: i32,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<Location, ApplicationError> {
+        diesel::update(
+            locations::table
+                .find(search_id)
+                .filter(locations::company.eq(&actor.company)),
+        )
+        .set(locations::name.eq(&self.name))
+        .get_result::<Location>(conn)
+        .map_err(name_conflict)
+    }
+}
@@ -0,0 +1,58 @@
+use crate::errors::application_error::ApplicationError;
+use crate::schema::login_attempts;
+use chrono::{Local, NaiveDateTime};
+use diesel::PgConnection;
+use diesel::RunQueryDsl;
+
+// Struct for inserting a login attempt into the audit table
+#[derive(Insertable, Debug)]
+#[table_name = "login_attempts"]
+pub struct LoginAttempt<'a> {
+    pub email: &'a str,
+    pub ip_address: &'a str,
+    pub succeeded: bool,
+    pub reason: Option<&'a str>,
+    pub created_at: NaiveDateTime,
+}
+
+// Longest prefix of `value` within `max_chars`, so any attempt fits the columns
+fn truncate(value: &str, max_chars: usize) -> &str {
+    match value.char_indices().nth(max_chars) {
+        Some((end, _)) => &value[..end],
+        None => value,
+    }
+}
+
+impl<'a> LoginAttempt<'a> {
+    pub fn new(email: &'a str, ip_address: &'a str, reason: Option<&'a str>) -> Self {
+        LoginAttempt {
+            email: truncate(email, 100),
+            ip_address: truncate(ip_address, 45),
+            succeeded: reason.is_none(),
+            reason,
+            created_at: Local::now().naive_local(),
+        }
+    }
+
+    pub fn record(&self, conn: &PgConnection) -> Result<(), ApplicationError> {
+        diesel::insert_into(login_attempts::table)
+            .values(self)
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn truncates_to_the_column_sizes() {
+        let email = format!("{}@example.com", "é".repeat(120));
+        let attempt = LoginAttempt::new(&email, "203.0.113.7", None);
+        assert_eq!(attempt.email.chars().count(), 100);
+        assert!(email.starts_with(attempt.email));
+        assert_eq!(attempt.ip_address, "203.0.113.7");
+        assert!(attempt.succeeded);
+    }
+}
@@ -1,2 +1,15 @@
+pub mod api_key;
+pub mod category;
+pub mod location;
+pub mod login_attempt;
+pub mod money;
+pub mod password_reset;
 pub mod product;
+pub mod product_csv;
+pub mod product_variant;
+pub mod refresh_token;
+pub mod revoked_token;
+pub mod role;
+pub mod stock_movement;
+pub mod two_factor;
 pub mod user;
@@ -0,0 +1,266 @@
+use std::fmt;
+use std::ops::{Add, Sub};
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use validator::ValidationError;
+
+// ISO 4217 currencies we accept with the number of digits of their minor unit
+const CURRENCIES: &[(&str, u32)] = &[
+    ("AUD", 2),
+    ("BHD", 3),
+    ("BRL", 2),
+    ("CAD", 2),
+    ("CHF", 2),
+    ("CLP", 0),
+    ("CNY", 2),
+    ("CZK", 2),
+    ("DKK", 2),
+    ("EUR", 2),
+    ("GBP", 2),
+    ("HKD", 2),
+    ("HUF", 2),
+    ("IDR", 2),
+    ("ILS", 2),
+    ("INR", 2),
+    ("ISK", 0),
+    ("JOD", 3),
+    ("JPY", 0),
+    ("KRW", 0),
+    ("KWD", 3),
+    ("MXN", 2),
+    ("MYR", 2),
+    ("NOK", 2),
+    ("NZD", 2),
+    ("OMR", 3),
+    ("PHP", 2),
+    ("PLN", 2),
+    ("SEK", 2),
+    ("SGD", 2),
+    ("THB", 2),
+    ("TND", 3),
+    ("TRY", 2),
+    ("TWD", 2),
+    ("USD", 2),
+    ("VND", 0),
+    ("ZAR", 2),
+];
+
+pub const DEFAULT_CURRENCY: &str = "USD";
+
+// Number of digits of the minor unit of a currency, `None` if unsupported
+pub fn minor_unit_digits(currency: &str) -> Option<u32> {
+    CURRENCIES
+        .iter()
+        .find(|(code, _)| *code == currency)
+        .map(|(_, digits)| *digits)
+}
+
+// An amount of money in the minor unit of its currency, e.g. 1999 USD is
+// 19.99 US dollars.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Money {
+    pub amount_minor: i64,
+    pub currency: String,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum MoneyError {
+    CurrencyMismatch(String, String),
+    Overflow,
+}
+
+impl fmt::Display for MoneyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MoneyError::CurrencyMismatch(left, right) => {
+                write!(f, "Cannot combine amounts in {} and {}", left, right)
+            }
+            MoneyError::Overflow => f.write_str("Amount is out of range"),
+        }
+    }
+}
+
+impl Money {
+    pub fn new(amount_minor: i64, currency: &str) -> Money {
+        Money {
+            amount_minor,
+            currency: currency.to_string(),
+        }
+    }
+
+    // Amount in major units as a decimal string, e.g. "19.99"
+    pub fn to_decimal_string(&self) -> String {
+        let digits = minor_unit_digits(&self.currency).unwrap_or(2);
+        if digits == 0 {
+            return self.amount_minor.to_string();
+        }
+        let factor = 10i64.pow(digits);
+        let sign = if self.amount_minor < 0 { "-" } else { "" };
+        let abs = self.amount_minor.unsigned_abs();
+        format!(
+            "{}{}.{:0width$}",
+            sign,
+            abs / factor as u64,
+            abs % factor as u64,
+            width = digits as usize
+        )
+    }
+
+    fn combine(self, other: Money, op: fn(i64, i64) -> Option<i64>) -> Result<Money, MoneyError> {
+        if self.currency != other.currency {
+            return Err(MoneyError::CurrencyMismatch(self.currency, other.currency));
+        }
+        let amount_minor = op(self.amount_minor, other.amount_minor).ok_or(MoneyError::Overflow)?;
+        Ok(Money {
+            amount_minor,
+            currency: self.currency,
+        })
+    }
+}
+
+// Adding or subtracting amounts in different currencies is an error
+impl Add for Money {
+    type Output = Result<Money, MoneyError>;
+
+    fn add(self, other: Money) -> Self::Output {
+        self.combine(other, i64::checked_add)
+    }
+}
+
+impl Sub for Money {
+    type Output = Result<Money, MoneyError>;
+
+    fn sub(self, other: Money) -> Self::Output {
+        self.combine(other, i64::checked_sub)
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.to_decimal_string(), self.currency)
+    }
+}
+
+// Wire format of `Money`. `amount` is informational and ignored on input.
+#[derive(Serialize, Deserialize)]
+struct MoneyRepr {
+    amount_minor: i64,
+    currency: String,
+    #[serde(default, skip_deserializing)]
+    amount: String,
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        MoneyRepr {
+            amount_minor: self.amount_minor,
+            currency: self.currency.clone(),
+            amount: self.to_decimal_string(),
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Money, D::Error> {
+        let repr = MoneyRepr::deserialize(deserializer)?;
+        Ok(Money {
+            amount_minor: repr.amount_minor,
+            currency: repr.currency,
+        })
+    }
+}
+
+// Validation rule for prices: a supported currency and an amount that fits
+// the products.price column
+pub fn validate_price(price: &Money) -> Result<(), ValidationError> {
+    if minor_unit_digits(&price.currency).is_none() {
+        let mut error = ValidationError::new("currency");
+        error.message = Some("Currency must be a supported ISO 4217 code".into());
+        return Err(error);
+    }
+    if !(0..=100_000_000).contains(&price.amount_minor) {
+        let mut error = ValidationError::new("range");
+        error.message = Some("Price must be between 0 and 100000000 minor units".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn adds_and_subtracts_in_the_same_currency() {
+        let sum = Money::new(1999, "EUR") + Money::new(1, "EUR");
+        assert_eq!(sum, Ok(Money::new(2000, "EUR")));
+        let difference = Money::new(500, "USD") - Money::new(750, "USD");
+        assert_eq!(difference, Ok(Money::new(-250, "USD")));
+    }
+
+    #[test]
+    fn rejects_mixed_currencies() {
+        let sum = Money::new(100, "EUR") + Money::new(100, "USD");
+        assert_eq!(
+            sum,
+            Err(MoneyError::CurrencyMismatch(
+                "EUR".to_string(),
+                "USD".to_string()
+            ))
+        );
+        assert_eq!(
+            MoneyError::CurrencyMismatch("EUR".to_string(), "USD".to_string()).to_string(),
+            "Cannot combine amounts in EUR and USD"
+        );
+    }
+
+    #[test]
+    fn reports_overflow() {
+        assert_eq!(
+            Money::new(i64::MAX, "USD") + Money::new(1, "USD"),
+            Err(MoneyError::Overflow)
+        );
+        assert_eq!(
+            Money::new(i64::MIN, "USD") - Money::new(1, "USD"),
+            Err(MoneyError::Overflow)
+        );
+    }
+
+    #[test]
+    fn formats_with_the_digits_of_the_currency() {
+        assert_eq!(Money::new(1999, "USD").to_decimal_string(), "19.99");
+        assert_eq!(Money::new(-5, "EUR").to_decimal_string(), "-0.05");
+        assert_eq!(Money::new(1500, "JPY").to_decimal_string(), "1500");
+        assert_eq!(Money::new(1234, "KWD").to_string(), "1.234 KWD");
+    }
+
+    #[test]
+    fn validates_prices() {
+        assert!(validate_price(&Money::new(0, "USD")).is_ok());
+        assert!(validate_price(&Money::new(100_000_000, "JPY")).is_ok());
+
+        let error = validate_price(&Money::new(100, "XYZ")).unwrap_err();
+        assert_eq!(error.code, "currency");
+        assert_eq!(
+            validate_price(&Money::new(-1, "USD")).unwrap_err().code,
+            "range"
+        );
+        assert_eq!(
+            validate_price(&Money::new(100_000_001, "USD"))
+                .unwrap_err()
+                .code,
+            "range"
+        );
+    }
+
+    #[test]
+    fn ignores_the_decimal_amount_on_input() {
+        let money: Money =
+            serde_json::from_str(r#"{"amount_minor":250,"currency":"GBP","amount":"9.99"}"#)
+                .unwrap();
+        assert_eq!(money, Money::new(250, "GBP"));
+        let json = serde_json::to_value(&money).unwrap();
+        assert_eq!(json["amount"], "2.50");
+    }
+}
@@ -0,0 +1,122 @@
+use crate::diesel::ExpressionMethods;
+use crate::errors::application_error::ApplicationError;
+use crate::models::user::User;
+use crate::schema::password_reset_tokens;
+use crate::schema::password_reset_tokens::dsl::*;
+use crate::schema::users;
+use crate::utils::token::{generate_token, hash_token};
+use crate::utils::validation::validate_password_strength;
+use chrono::{Duration, Local, NaiveDateTime};
+use diesel::Connection;
+use diesel::OptionalExtension;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use serde::Deserialize;
+use validator::Validate;
+
+// How long a reset token stays valid
+pub const RESET_TOKEN_TTL_MINUTES: i64 = 30;
+
+// Create a struct to represent a password reset token.
+#[derive(Queryable, Debug)]
+pub struct PasswordResetToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+// Struct for inserting a new reset token into database
+#[derive(Insertable, Debug)]
+#[table_name = "password_reset_tokens"]
+struct NewPasswordResetToken {
+    user_id: i32,
+    token_hash: String,
+    expires_at: NaiveDateTime,
+    created_at: NaiveDateTime,
+}
+
+// Forgot password model
+#[derive(Deserialize, Validate)]
+pub struct ForgotPassword {
+    #[validate(email(message = "Email must be a valid email address"))]
+    pub email: String,
+}
+
+impl ForgotPassword {
+    // Issue a reset token for the user with this email. Returns the plain
+    // token and the user, or `None` when no such user exists.
+    pub fn issue(&self, conn: &PgConnection) -> Result<Option<(String, User)>, ApplicationError> {
+        let user = users::table
+            .filter(users::email.eq(&self.email))
+            .first::<User>(conn)
+            .optional()?;
+        let user = match user {
+            Some(user) => user,
+            None => return Ok(None),
+        };
+
+        let token = generate_token();
+        let now = Local::now().naive_local();
+        diesel::insert_into(password_reset_tokens::table)
+            .values(&NewPasswordResetToken {
+                user_id: user.id,
+                token_hash: hash_token(&token),
+                expires_at: now + Duration::minutes(RESET_TOKEN_TTL_MINUTES),
+                created_at: now,
+            })
+            .execute(conn)?;
+        Ok(Some((token, user)))
+    }
+}
+
+// Reset password model
+#[derive(Deserialize, Validate)]
+pub struct ResetPassword {
+    pub token: String,
+    #[validate(
+        custom = "validate_password_strength",
+        must_match(
+            other = "password_confirmation",
+            message = "Password and password confirmation do not match"
+        )
+    )]
+    pub password: String,
+    pub password_confirmation: String,
+}
+
+impl ResetPassword {
+    // Consume the token, set the new password and invalidate every session
+    // issued to the user before now.
+    pub fn reset(&self, conn: &PgConnection) -> Result<User, ApplicationError> {
+        let hashed_password = User::hash_password(&self.password)?;
+
+        conn.transaction(|| {
+            let now = Local::now().naive_local();
+            let reset_token = password_reset_tokens
+                .filter(token_hash.eq(hash_token(&self.token)))
+                .filter(used_at.is_null())
+                .filter(expires_at.gt(now))
+                .for_update()
+                .first::<PasswordResetToken>(conn)
+                .optional()?
+                .ok_or_else(|| {
+                    ApplicationError::InvalidToken("Invalid or expired token".to_string())
+                })?;
+
+            // burn this and every other outstanding token of the user
+            diesel::update(
+                password_reset_tokens
+                    .filter(user_id.eq(reset_token.user_id))
+                    .filter(used_at.is_null()),
+            )
+            .set(used_at.eq(Some(now)))
+            .execute(conn)?;
+
+            User::replace_password(reset_token.user_id, &hashed_password, conn)
+        })
+    }
+}
@@ -1,86 +1,661 @@
-use crate::diesel::ExpressionMethods;
-use crate::schema::products::dsl::*;
+use crate::diesel::{ExpressionMethods, PgTextExpressionMethods, TextExpressionMethods};
+use crate::errors::application_error::ApplicationError;
+use crate::models::category::Category;
+use crate::models::location::{Location, LocationStock};
+use crate::models::money::{validate_price, Money, DEFAULT_CURRENCY};
+use crate::models::stock_movement::{MovementKind, StockMovement, StockTarget};
+use crate::utils::jwt::SlimUser;
+use data_encoding::BASE64URL_NOPAD;
+use diesel::pg::Pg;
+use diesel::sql_types::{BigInt, Double, Varchar};
+use diesel::Connection;
 use diesel::PgConnection;
 use diesel::QueryDsl;
+use diesel::Queryable;
 use diesel::RunQueryDsl;
 
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
 // use product table in schema file
-use crate::schema::products;
+use crate::schema::{categories, product_categories, products};
 
 // Create a struct to represent a product.
-#[derive(Queryable, Serialize, Deserialize)]
+#[derive(Serialize, Deserialize)]
 pub struct Product {
     pub id: i32,
     pub name: String,
     pub stock: f64,
-    pub price: Option<i32>,
+    pub price: Option<Money>,
+    #[serde(skip)]
+    pub company: String,
+}
+
+// A product with its stock broken down by location, `stock` staying the
+// total and `unallocated_stock` what no location holds, negative when the
+// locations hold more than the total
+#[derive(Serialize)]
+pub struct ProductDetail {
+    #[serde(flatten)]
+    pub product: Product,
+    pub stock_by_location: Vec<LocationStock>,
+    pub unallocated_stock: f64,
 }
 
+// products.price and products.currency are combined into a single `Money`
+impl Queryable<products::SqlType, Pg> for Product {
+    type Row = (i32, String, f64, Option<i32>, String, String);
+
+    fn build(row: Self::Row) -> Self {
+        let (product_id, product_name, product_stock, price, company, currency) = row;
+        Product {
+            id: product_id,
+            name: product_name,
+            stock: product_stock,
+            price: price.map(|amount| Money::new(amount.into(), &currency)),
+            company,
+        }
+    }
+}
+
+// Stock of one company's products, see `Product::stock_by_company`
+#[derive(QueryableByName, Debug)]
+pub struct CompanyStock {
+    #[sql_type = "Varchar"]
+    pub company: String,
+    #[sql_type = "Double"]
+    pub total: f64,
+    #[sql_type = "BigInt"]
+    pub out_of_stock: i64,
+}
+
+// Every product lookup is scoped to the company that owns it, so a product of
+// another company behaves exactly like a missing one.
 impl Product {
     pub fn find(
         search_id: &i32,
+        owner: &str,
         connection: &PgConnection,
     ) -> Result<Product, diesel::result::Error> {
-        let product = products.find(search_id).first(connection)?;
+        let product = products::table
+            .find(search_id)
+            .filter(products::company.eq(owner))
+            .first(connection)?;
         Ok(product)
     }
 
+    pub fn detail(
+        search_id: &i32,
+        owner: &str,
+        connection: &PgConnection,
+    ) -> Result<ProductDetail, ApplicationError> {
+        let product = Product::find(search_id, owner, connection)?;
+        let stock_by_location = Location::stock_of_product(product.id, connection)?;
+        let allocated: f64 = stock_by_location.iter().map(|level| level.stock).sum();
+        if allocated > product.stock {
+            log::error!(
+                "Product {} has {} in locations but a total stock of {}",
+                product.id,
+                allocated,
+                product.stock
+            );
+        }
+        Ok(ProductDetail {
+            unallocated_stock: product.stock - allocated,
+            product,
+            stock_by_location,
+        })
+    }
+
+    // Stock summed per company by the database, used for metrics
+    pub fn stock_by_company(
+        connection: &PgConnection,
+    ) -> Result<Vec<CompanyStock>, diesel::result::Error> {
+        diesel::sql_query(
+            "SELECT company, COALESCE(SUM(stock), 0) AS total, \
+             COUNT(*) FILTER (WHERE stock <= 0) AS out_of_stock \
+             FROM products GROUP BY company ORDER BY company",
+        )
+        .load(connection)
+    }
+
     // Delete a product by id
     pub fn destroy(
         search_id: &i32,
+        owner: &str,
         connection: &PgConnection,
     ) -> Result<(), diesel::result::Error> {
-        diesel::delete(products.filter(id.eq(search_id))).execute(connection)?;
+        let deleted = diesel::delete(
+            products::table
+                .filter(products::id.eq(search_id))
+                .filter(products::company.eq(owner)),
+        )
+        .execute(connection)?;
+        if deleted == 0 {
+            return Err(diesel::result::Error::NotFound);
+        }
         Ok(())
     }
 
-    // Update a product by id
+    // Update a product by id. A stock change is recorded in the ledger as
+    // an adjustment rather than silently overwriting the value.
     pub fn update(
         search_id: &i32,
         new_product: &NewProduct,
+        actor: &SlimUser,
         connection: &PgConnection,
-    ) -> Result<Product, diesel::result::Error> {
-        let updated_product = diesel::update(products.find(search_id))
-            .set(new_product)
-            .get_result::<Product>(connection)?;
-        Ok(updated_product)
+    ) -> Result<Product, ApplicationError> {
+        connection.transaction(|| {
+            let current = products::table
+                .find(search_id)
+                .filter(products::company.eq(&actor.company))
+                .for_update()
+                .first::<Product>(connection)?;
+            if let Some(new_stock) = new_product.stock {
+                let delta = new_stock - current.stock;
+                if delta < 0.0 {
+                    StockTarget::product(current.id).check_stock_edit(new_stock, connection)?;
+                }
+                if delta != 0.0 {
+                    StockMovement::apply(
+                        *search_id,
+                        MovementKind::Adjustment,
+                        delta,
+                        "Product update",
+                        actor,
+                        connection,
+                    )?;
+                }
+            }
+            let changes = ProductChangeset {
+                stock: None,
+                ..new_product.into()
+            };
+            let updated_product = if changes.name.is_none() && changes.price.is_none() {
+                Product::find(search_id, &actor.company, connection)?
+            } else {
+                diesel::update(products::table.find(search_id))
+                    .set(&changes)
+                    .get_result::<Product>(connection)?
+            };
+            Ok(updated_product)
+        })
     }
 }
 
 /// Create Product
 // Create a new product.
-#[derive(Insertable, Deserialize, AsChangeset)]
-#[table_name = "products"]
+#[derive(Deserialize, Clone, Validate)]
 pub struct NewProduct {
+    #[validate(length(
+        min = 1,
+        max = 255,
+        message = "Name must be between 1 and 255 characters long"
+    ))]
     pub name: Option<String>,
+    #[validate(range(min = 0.0, message = "Stock must not be negative"))]
     pub stock: Option<f64>,
-    pub price: Option<i32>,
+    #[validate(custom = "validate_price")]
+    pub price: Option<Money>,
+}
+
+// Body of a product creation, a `NewProduct` whose name is required, so a
+// single validation pass reports every field
+#[derive(Deserialize, Validate)]
+pub struct CreateProduct {
+    #[validate(
+        required(message = "Name is required"),
+        length(
+            min = 1,
+            max = 255,
+            message = "Name must be between 1 and 255 characters long"
+        )
+    )]
+    pub name: Option<String>,
+    #[validate(range(min = 0.0, message = "Stock must not be negative"))]
+    pub stock: Option<f64>,
+    #[validate(custom = "validate_price")]
+    pub price: Option<Money>,
+}
+
+impl From<CreateProduct> for NewProduct {
+    fn from(create_product: CreateProduct) -> Self {
+        NewProduct {
+            name: create_product.name,
+            stock: create_product.stock,
+            price: create_product.price,
+        }
+    }
+}
+
+// Columns written when creating or updating a product
+#[derive(Insertable, AsChangeset)]
+#[table_name = "products"]
+struct ProductChangeset<'a> {
+    name: Option<&'a str>,
+    stock: Option<f64>,
+    price: Option<i32>,
+    currency: Option<&'a str>,
+}
+
+impl<'a> From<&'a NewProduct> for ProductChangeset<'a> {
+    fn from(new_product: &'a NewProduct) -> Self {
+        ProductChangeset {
+            name: new_product.name.as_deref(),
+            stock: new_product.stock,
+            // validation keeps the amount within the range of the column
+            price: new_product
+                .price
+                .as_ref()
+                .map(|price| price.amount_minor as i32),
+            currency: new_product
+                .price
+                .as_ref()
+                .map(|price| price.currency.as_str()),
+        }
+    }
 }
 
 impl NewProduct {
-    pub fn create(&self, connection: &PgConnection) -> Result<Product, diesel::result::Error> {
-        // Insert the new product into the database.
-        diesel::insert_into(products)
-            .values(self)
-            .get_result(connection)
+    pub fn create(
+        &self,
+        actor: &SlimUser,
+        connection: &PgConnection,
+    ) -> Result<Product, ApplicationError> {
+        connection.transaction(|| {
+            // Insert the new product with no stock, then book the initial
+            // stock through the ledger.
+            let product: Product = diesel::insert_into(products::table)
+                .values((
+                    &ProductChangeset {
+                        stock: Some(0.0),
+                        currency: Some(
+                            self.price
+                                .as_ref()
+                                .map(|price| price.currency.as_str())
+                                .unwrap_or(DEFAULT_CURRENCY),
+                        ),
+                        ..self.into()
+                    },
+                    products::company.eq(&actor.company),
+                ))
+                .get_result(connection)?;
+            match self.stock {
+                Some(initial) if initial != 0.0 => {
+                    StockMovement::apply(
+                        product.id,
+                        MovementKind::Adjustment,
+                        initial,
+                        "Initial stock",
+                        actor,
+                        connection,
+                    )?;
+                    Ok(Product::find(&product.id, &actor.company, connection)?)
+                }
+                _ => Ok(product),
+            }
+        })
     }
 }
 
+/// List Products
+// Query parameters accepted by the product listing.
+#[derive(Deserialize, Serialize, Default, Clone)]
+pub struct ProductQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    // opaque cursor returned in `links.cursor` and, once used, in `links.next`;
+    // switches to keyset pagination on id
+    pub cursor: Option<String>,
+    pub name: Option<String>,
+    pub min_stock: Option<f64>,
+    pub max_stock: Option<f64>,
+    // price bounds are in minor units of `currency`, which they require
+    pub min_price: Option<i32>,
+    pub max_price: Option<i32>,
+    pub currency: Option<String>,
+    // products in the category or any of its subcategories
+    pub category: Option<i32>,
+    pub sort: Option<SortField>,
+    pub order: Option<SortOrder>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortField {
+    Id,
+    Name,
+    Stock,
+    Price,
+}
+
+#[derive(Deserialize, Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum SortOrder {
+    Asc,
+    Desc,
+}
+
+pub const DEFAULT_PAGE_LIMIT: i64 = 20;
+pub const MAX_PAGE_LIMIT: i64 = 100;
+
+impl ProductQuery {
+    pub fn page(&self) -> i64 {
+        self.page.unwrap_or(1).max(1)
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.limit
+            .unwrap_or(DEFAULT_PAGE_LIMIT)
+            .clamp(1, MAX_PAGE_LIMIT)
+    }
+
+    // Decode the cursor into the last id seen by the client.
+    pub fn cursor_id(&self) -> Result<Option<i32>, ApplicationError> {
+        match &self.cursor {
+            None => Ok(None),
+            Some(cursor) => BASE64URL_NOPAD
+                .decode(cursor.as_bytes())
+                .ok()
+                .and_then(|bytes| String::from_utf8(bytes).ok())
+                .and_then(|value| value.parse::<i32>().ok())
+                .map(Some)
+                .ok_or_else(|| ApplicationError::InvalidQuery("Invalid cursor".to_string())),
+        }
+    }
+
+    pub fn encode_cursor(last_id: i32) -> String {
+        BASE64URL_NOPAD.encode(last_id.to_string().as_bytes())
+    }
+
+    // Build the filtered (but unsorted and unpaginated) query over the
+    // products of `owner`. `category_pattern` matches the paths of the
+    // category filter and its descendants.
+    fn filtered(
+        &self,
+        owner: &str,
+        category_pattern: Option<&str>,
+    ) -> products::BoxedQuery<'static, Pg> {
+        let mut query = products::table
+            .filter(products::company.eq(owner.to_string()))
+            .into_boxed();
+        if let Some(search) = &self.name {
+            query = query.filter(products::name.ilike(format!("%{}%", escape_like(search))));
+        }
+        if let Some(min) = self.min_stock {
+            query = query.filter(products::stock.ge(min));
+        }
+        if let Some(max) = self.max_stock {
+            query = query.filter(products::stock.le(max));
+        }
+        if let Some(price_currency) = &self.currency {
+            query = query.filter(products::currency.eq(price_currency.to_uppercase()));
+        }
+        if let Some(min) = self.min_price {
+            query = query.filter(products::price.ge(min));
+        }
+        if let Some(max) = self.max_price {
+            query = query.filter(products::price.le(max));
+        }
+        if let Some(pattern) = category_pattern {
+            query = query.filter(
+                products::id.eq_any(
+                    product_categories::table
+                        .inner_join(categories::table)
+                        .filter(categories::company.eq(owner.to_string()))
+                        .filter(categories::path.like(pattern.to_string()))
+                        .select(product_categories::product_id),
+                ),
+            );
+        }
+        query
+    }
+
+    pub fn validate(&self) -> Result<(), ApplicationError> {
+        // prices are only comparable within one currency
+        let uses_price = self.min_price.is_some()
+            || self.max_price.is_some()
+            || self.sort == Some(SortField::Price);
+        if uses_price && self.currency.is_none() {
+            return Err(ApplicationError::InvalidQuery(
+                "currency is required to filter or sort by price".to_string(),
+            ));
+        }
+        if self.cursor.is_some() {
+            if self.page.is_some() {
+                return Err(ApplicationError::InvalidQuery(
+                    "page and cursor cannot be combined".to_string(),
+                ));
+            }
+            if self.sort.unwrap_or(SortField::Id) != SortField::Id {
+                return Err(ApplicationError::InvalidQuery(
+                    "cursor pagination only supports sort=id".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Escape LIKE wildcards so the search term is matched literally.
+fn escape_like(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('%', "\\%")
+        .replace('_', "\\_")
+}
+
 /// Get Product
-// Get a list of all products.
+// A page of products together with paging metadata.
 #[derive(Serialize, Deserialize)]
-pub struct ProductsList(pub Vec<Product>);
+pub struct ProductsList {
+    pub data: Vec<Product>,
+    pub total: i64,
+    pub page: Option<i64>,
+    pub limit: i64,
+    pub links: PageLinks,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PageLinks {
+    pub next: Option<String>,
+    pub prev: Option<String>,
+    // in page mode, the next page by keyset pagination from the last product
+    // of this one, only offered for sort=id
+    pub cursor: Option<String>,
+}
 
 impl ProductsList {
-    pub fn list(connection: &PgConnection) -> ProductsList {
-        // Get all products from the database.
-        let result = products
-            .load::<Product>(connection)
-            .expect("Error loading products");
-
-        // Return the list of products.
-        ProductsList(result)
+    // Run the listing query; `base_url` is used to render the page links.
+    pub fn query(
+        params: &ProductQuery,
+        owner: &str,
+        base_url: &str,
+        connection: &PgConnection,
+    ) -> Result<ProductsList, ApplicationError> {
+        params.validate()?;
+        let limit = params.limit();
+        let category_pattern = params
+            .category
+            .map(|category_id| Category::subtree_pattern(category_id, owner, connection))
+            .transpose()?;
+        let total = params
+            .filtered(owner, category_pattern.as_deref())
+            .count()
+            .get_result::<i64>(connection)?;
+
+        let mut query = params.filtered(owner, category_pattern.as_deref());
+        let order = params.order.unwrap_or(SortOrder::Asc);
+        query = match (params.sort.unwrap_or(SortField::Id), order) {
+            (SortField::Id, SortOrder::Asc) => query.order(products::id.asc()),
+            (SortField::Id, SortOrder::Desc) => query.order(products::id.desc()),
+            (SortField::Name, SortOrder::Asc) => {
+                query.order((products::name.asc(), products::id.asc()))
+            }
+            (SortField::Name, SortOrder::Desc) => {
+                query.order((products::name.desc(), products::id.desc()))
+            }
+            (SortField::Stock, SortOrder::Asc) => {
+                query.order((products::stock.asc(), products::id.asc()))
+            }
+            (SortField::Stock, SortOrder::Desc) => {
+                query.order((products::stock.desc(), products::id.desc()))
+            }
+            (SortField::Price, SortOrder::Asc) => {
+                query.order((products::price.asc(), products::id.asc()))
+            }
+            (SortField::Price, SortOrder::Desc) => {
+                query.order((products::price.desc(), products::id.desc()))
+            }
+        };
+
+        let cursor_id = params.cursor_id()?;
+        query = match (cursor_id, order) {
+            // keyset pagination, only valid for sort=id
+            (Some(last_id), SortOrder::Asc) => query.filter(products::id.gt(last_id)),
+            (Some(last_id), SortOrder::Desc) => query.filter(products::id.lt(last_id)),
+            (None, _) => query.offset((params.page() - 1) * limit),
+        };
+
+        // fetch one extra row to know whether there is a next page
+        let mut data = query.limit(limit + 1).load::<Product>(connection)?;
+        let has_next = data.len() as i64 > limit;
+        data.truncate(limit as usize);
+
+        let link = |query: ProductQuery| {
+            serde_urlencoded::to_string(&query)
+                .map(|qs| format!("{}?{}", base_url, qs))
+                .ok()
+        };
+        let cursor_link = || match data.last() {
+            Some(last) if has_next => link(ProductQuery {
+                page: None,
+                cursor: Some(ProductQuery::encode_cursor(last.id)),
+                ..params.clone()
+            }),
+            _ => None,
+        };
+        let (page, links) = if cursor_id.is_some() {
+            let links = PageLinks {
+                next: cursor_link(),
+                ..PageLinks::default()
+            };
+            (None, links)
+        } else {
+            let page = params.page();
+            let next = if has_next {
+                link(ProductQuery {
+                    page: Some(page + 1),
+                    ..params.clone()
+                })
+            } else {
+                None
+            };
+            let prev = if page > 1 {
+                link(ProductQuery {
+                    page: Some(page - 1),
+                    ..params.clone()
+                })
+            } else {
+                None
+            };
+            let cursor = if params.sort.unwrap_or(SortField::Id) == SortField::Id {
+                cursor_link()
+            } else {
+                None
+            };
+            (Some(page), PageLinks { next, prev, cursor })
+        };
+
+        Ok(ProductsList {
+            data,
+            total,
+            page,
+            limit,
+            links,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escapes_like_wildcards() {
+        assert_eq!(escape_like("100%_pure\\"), "100\\%\\_pure\\\\");
+        assert_eq!(escape_like("plain"), "plain");
+    }
+
+    #[test]
+    fn round_trips_cursors() {
+        let query = ProductQuery {
+            cursor: Some(ProductQuery::encode_cursor(42)),
+            ..ProductQuery::default()
+        };
+        assert_eq!(query.cursor_id().unwrap(), Some(42));
+        assert_eq!(ProductQuery::default().cursor_id().unwrap(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_cursors() {
+        for cursor in ["not base64!", "YWJj", ""] {
+            let query = ProductQuery {
+                cursor: Some(cursor.to_string()),
+                ..ProductQuery::default()
+            };
+            assert!(
+                matches!(query.cursor_id(), Err(ApplicationError::InvalidQuery(_))),
+                "{:?} was accepted",
+                cursor
+            );
+        }
+    }
+
+    #[test]
+    fn clamps_page_and_limit() {
+        let query = ProductQuery {
+            page: Some(0),
+            limit: Some(1000),
+            ..ProductQuery::default()
+        };
+        assert_eq!(query.page(), 1);
+        assert_eq!(query.limit(), MAX_PAGE_LIMIT);
+        assert_eq!(ProductQuery::default().limit(), DEFAULT_PAGE_LIMIT);
+    }
+
+    #[test]
+    fn validates_query_combinations() {
+        let cursor_with_page = ProductQuery {
+            page: Some(2),
+            cursor: Some(ProductQuery::encode_cursor(1)),
+            ..ProductQuery::default()
+        };
+        assert!(cursor_with_page.validate().is_err());
+
+        let price_without_currency = ProductQuery {
+            sort: Some(SortField::Price),
+            ..ProductQuery::default()
+        };
+        assert!(price_without_currency.validate().is_err());
+
+        let price_with_currency = ProductQuery {
+            min_price: Some(100),
+            currency: Some("EUR".to_string()),
+            ..ProductQuery::default()
+        };
+        assert!(price_with_currency.validate().is_ok());
+    }
+
+    #[test]
+    fn reports_a_missing_name_with_the_other_field_errors() {
+        let create_product: CreateProduct = serde_json::from_value(serde_json::json!({
+            "price": {"amount_minor": 100, "currency": "eur"}
+        }))
+        .unwrap();
+        let errors = create_product.validate().unwrap_err();
+        let mut fields: Vec<_> = errors.field_errors().into_keys().collect();
+        fields.sort();
+        assert_eq!(fields, ["name", "price"]);
     }
 }
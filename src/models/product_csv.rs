@@ -0,0 +1,234 @@
+use std::io::Read;
+
+use crate::diesel::ExpressionMethods;
+use crate::errors::application_error::ApplicationError;
+use crate::models::money::Money;
+use crate::models::product::{NewProduct, Product};
+use crate::schema::products;
+use crate::utils::jwt::SlimUser;
+use crate::utils::validation::field_errors;
+use diesel::Connection;
+use diesel::OptionalExtension;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+// Number of products loaded per query while exporting
+pub const EXPORT_BATCH_SIZE: i64 = 500;
+
+// One line of the catalog CSV, used for both import and export.
+// `id` is optional on import: without it products are matched by name.
+#[derive(Deserialize, Serialize)]
+pub struct ProductCsvRow {
+    pub id: Option<i32>,
+    pub name: String,
+    pub stock: f64,
+    pub price_minor: Option<i64>,
+    pub currency: Option<String>,
+}
+
+impl From<&Product> for ProductCsvRow {
+    fn from(product: &Product) -> Self {
+        ProductCsvRow {
+            id: Some(product.id),
+            name: product.name.clone(),
+            stock: product.stock,
+            price_minor: product.price.as_ref().map(|price| price.amount_minor),
+            currency: product.price.as_ref().map(|price| price.currency.clone()),
+        }
+    }
+}
+
+impl ProductCsvRow {
+    fn into_new_product(self) -> Result<NewProduct, String> {
+        let price = match (self.price_minor, self.currency) {
+            (Some(amount), Some(currency)) => Some(Money::new(amount, &currency.to_uppercase())),
+            (None, None) => None,
+            _ => return Err("price_minor and currency must be given together".to_string()),
+        };
+        Ok(NewProduct {
+            name: Some(self.name),
+            stock: Some(self.stock),
+            price,
+        })
+    }
+}
+
+// Error of a single CSV line, `line` is the 1-based line in the file
+#[derive(Serialize)]
+pub struct RowError {
+    pub line: u64,
+    pub errors: Vec<String>,
+}
+
+// Outcome of an import
+#[derive(Serialize, Default)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub rows: u64,
+    pub created: u64,
+    pub updated: u64,
+    // rows of existing products left untouched by an insert-only import
+    pub skipped: u64,
+    pub errors: Vec<RowError>,
+}
+
+// What an import does with rows of products that already exist
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    Upsert,
+    // existing products keep their stock and price, e.g. when seeding again
+    InsertOnly,
+}
+
+enum RowOutcome {
+    Created,
+    Updated,
+    Skipped,
+}
+
+// Read the catalog CSV from `input` and upsert every row inside a single
+// transaction. The transaction is rolled back on a dry run or when any row
+// failed, so an import is all or nothing.
+pub fn import(
+    input: impl Read,
+    mode: ImportMode,
+    dry_run: bool,
+    actor: &SlimUser,
+    conn: &PgConnection,
+) -> Result<ImportReport, ApplicationError> {
+    let mut report = ImportReport {
+        dry_run,
+        ..ImportReport::default()
+    };
+    let mut reader = csv::Reader::from_reader(input);
+    let headers = reader
+        .headers()
+        .map_err(|err| ApplicationError::InvalidCsv(err.to_string()))?
+        .clone();
+    let mut record = csv::StringRecord::new();
+
+    let result = conn.transaction::<(), ApplicationError, _>(|| {
+        loop {
+            let outcome = match reader.read_record(&mut record) {
+                Ok(false) => break,
+                Ok(true) => record
+                    .deserialize::<ProductCsvRow>(Some(&headers))
+                    .map_err(|err| vec![err.to_string()])
+                    .and_then(|row| upsert_row(row, mode, actor, conn)),
+                // the upload itself failed, there is nothing left to read
+                Err(err) if matches!(err.kind(), csv::ErrorKind::Io(_)) => {
+                    return Err(ApplicationError::InvalidCsv(err.to_string()))
+                }
+                Err(err) => Err(vec![err.to_string()]),
+            };
+            report.rows += 1;
+            let line = record
+                .position()
+                .map(|position| position.line())
+                .unwrap_or(report.rows + 1);
+            match outcome {
+                Ok(RowOutcome::Created) => report.created += 1,
+                Ok(RowOutcome::Updated) => report.updated += 1,
+                Ok(RowOutcome::Skipped) => report.skipped += 1,
+                Err(errors) => report.errors.push(RowError { line, errors }),
+            }
+        }
+        if dry_run || !report.errors.is_empty() {
+            return Err(ApplicationError::DBError(
+                diesel::result::Error::RollbackTransaction,
+            ));
+        }
+        Ok(())
+    });
+
+    match result {
+        Ok(()) | Err(ApplicationError::DBError(diesel::result::Error::RollbackTransaction)) => {
+            Ok(report)
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Create or update the product of a row, an insert-only import skips it when
+// the product exists.
+// Every row runs in its own savepoint so a failing row does not abort the
+// rest of the import.
+fn upsert_row(
+    row: ProductCsvRow,
+    mode: ImportMode,
+    actor: &SlimUser,
+    conn: &PgConnection,
+) -> Result<RowOutcome, Vec<String>> {
+    let row_id = row.id;
+    let new_product = row.into_new_product().map_err(|err| vec![err])?;
+    new_product.validate().map_err(|errors| {
+        field_errors(&errors)
+            .into_iter()
+            .map(|error| format!("{}: {}", error.field, error.message))
+            .collect::<Vec<String>>()
+    })?;
+
+    let existing = match row_id {
+        Some(search_id) => Some(search_id),
+        None => products::table
+            .filter(products::company.eq(&actor.company))
+            .filter(products::name.eq(new_product.name.as_deref().unwrap_or_default()))
+            .select(products::id)
+            .order(products::id.asc())
+            .first::<i32>(conn)
+            .optional()
+            .map_err(|err| vec![err.to_string()])?,
+    };
+
+    let result = match existing {
+        Some(_) if mode == ImportMode::InsertOnly => Ok(RowOutcome::Skipped),
+        Some(search_id) => {
+            Product::update(&search_id, &new_product, actor, conn).map(|_| RowOutcome::Updated)
+        }
+        None => new_product.create(actor, conn).map(|_| RowOutcome::Created),
+    };
+    result.map_err(|err| match err {
+        ApplicationError::DBError(diesel::result::Error::NotFound) => {
+            vec![format!("Product {} not found", row_id.unwrap_or_default())]
+        }
+        _ => vec![err.to_string()],
+    })
+}
+
+// Load the next batch of products of `owner` after `after_id`, ordered by id
+pub fn export_batch(
+    owner: &str,
+    after_id: i32,
+    conn: &PgConnection,
+) -> Result<Vec<Product>, ApplicationError> {
+    let batch = products::table
+        .filter(products::company.eq(owner))
+        .filter(products::id.gt(after_id))
+        .order(products::id.asc())
+        .limit(EXPORT_BATCH_SIZE)
+        .load::<Product>(conn)?;
+    Ok(batch)
+}
+
+// Serialize rows as CSV, with the header line when `with_header` is set
+pub fn write_rows(rows: &[Product], with_header: bool) -> Result<Vec<u8>, ApplicationError> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(with_header)
+        .from_writer(Vec::new());
+    if with_header && rows.is_empty() {
+        writer
+            .write_record(["id", "name", "stock", "price_minor", "currency"])
+            .map_err(|err| ApplicationError::Csv(err.to_string()))?;
+    }
+    for product in rows {
+        writer
+            .serialize(ProductCsvRow::from(product))
+            .map_err(|err| ApplicationError::Csv(err.to_string()))?;
+    }
+    writer
+        .into_inner()
+        .map_err(|err| ApplicationError::Csv(err.to_string()))
+}
@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+
+use crate::diesel::ExpressionMethods;
+use crate::errors::application_error::ApplicationError;
+use crate::models::money::{validate_price, Money};
+use crate::models::product::Product;
+use crate::models::stock_movement::{MovementKind, StockMovement, StockTarget};
+use crate::schema::{product_variants, products, stock_levels};
+use crate::utils::jwt::SlimUser;
+use chrono::{Local, NaiveDateTime};
+use diesel::pg::Pg;
+use diesel::result::DatabaseErrorKind;
+use diesel::Connection;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::Queryable;
+use diesel::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use validator::{Validate, ValidationError};
+
+const MAX_OPTIONS: usize = 10;
+
+// Create a struct to represent a variant of a product, e.g. its size M in
+// red. `price` overrides the price of the product when set.
+#[derive(Serialize, Debug)]
+pub struct ProductVariant {
+    pub id: i32,
+    pub product_id: i32,
+    #[serde(skip)]
+    pub company: String,
+    pub sku: String,
+    pub options: serde_json::Value,
+    pub price: Option<Money>,
+    pub stock: f64,
+    pub created_at: NaiveDateTime,
+}
+
+type VariantRow = (
+    i32,
+    i32,
+    String,
+    String,
+    serde_json::Value,
+    Option<i32>,
+    Option<String>,
+    f64,
+    NaiveDateTime,
+);
+
+// price and currency are combined into a single `Money`, like products
+impl Queryable<product_variants::SqlType, Pg> for ProductVariant {
+    type Row = VariantRow;
+
+    fn build(row: Self::Row) -> Self {
+        let (id, product_id, company, sku, options, price, currency, stock, created_at) = row;
+        ProductVariant {
+            id,
+            product_id,
+            company,
+            sku,
+            options,
+            price: price
+                .zip(currency)
+                .map(|(amount, currency)| Money::new(amount.into(), &currency)),
+            stock,
+            created_at,
+        }
+    }
+}
+
+fn variant_conflict(err: diesel::result::Error) -> ApplicationError {
+    match &err {
+        diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, info) => {
+            let message = if info.constraint_name() == Some("product_variants_sku_idx") {
+                "A variant with this SKU already exists"
+            } else {
+                "The product already has a variant with these options"
+            };
+            ApplicationError::VariantConflict(message.to_string())
+        }
+        _ => ApplicationError::DBError(err),
+    }
+}
+
+// Lock the product so its stock and variants change one request at a time
+fn lock_product(
+    product_id: i32,
+    owner: &str,
+    conn: &PgConnection,
+) -> Result<Product, diesel::result::Error> {
+    products::table
+        .find(product_id)
+        .filter(products::company.eq(owner))
+        .for_update()
+        .first::<Product>(conn)
+}
+
+// Variants are reached through their product, which scopes them to its company
+impl ProductVariant {
+    pub fn for_product(
+        product_id: i32,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<Vec<ProductVariant>, ApplicationError> {
+        Product::find(&product_id, owner, conn)?;
+        Ok(product_variants::table
+            .filter(product_variants::product_id.eq(product_id))
+            .order(product_variants::id.asc())
+            .load::<ProductVariant>(conn)?)
+    }
+
+    pub fn find(
+        product_id: i32,
+        search_id: i32,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<ProductVariant, diesel::result::Error> {
+        product_variants::table
+            .find(search_id)
+            .filter(product_variants::product_id.eq(product_id))
+            .filter(product_variants::company.eq(owner))
+            .first(conn)
+    }
+
+    // Delete a variant, its remaining stock leaves the product and its
+    // locations through the ledger
+    pub fn destroy(
+        product_id: i32,
+        search_id: i32,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<(), ApplicationError> {
+        conn.transaction(|| {
+            lock_product(product_id, &actor.company, conn)?;
+            let variant = ProductVariant::find(product_id, search_id, &actor.company, conn)?;
+            let levels = stock_levels::table
+                .filter(stock_levels::variant_id.eq(variant.id))
+                .filter(stock_levels::quantity.gt(0.0))
+                .select((stock_levels::location_id, stock_levels::quantity))
+                .load::<(i32, f64)>(conn)?;
+            let target = StockTarget::variant(product_id, variant.id);
+            let held_nowhere = variant.stock - levels.iter().map(|(_, held)| held).sum::<f64>();
+            let bookings = levels
+                .into_iter()
+                .map(|(location_id, held)| (Some(location_id), held))
+                .chain(Some((None, held_nowhere)).filter(|(_, held)| *held > 0.0));
+            for (location_id, held) in bookings {
+                StockMovement::apply_to(
+                    target.at(location_id),
+                    MovementKind::Adjustment,
+                    -held,
+                    "Variant deleted",
+                    actor,
+                    conn,
+                )?;
+            }
+            diesel::delete(product_variants::table.find(variant.id)).execute(conn)?;
+            Ok(())
+        })
+    }
+}
+
+fn validate_sku(sku: &str) -> Result<(), ValidationError> {
+    let length = sku.chars().count();
+    if !(1..=64).contains(&length) || sku.chars().any(char::is_whitespace) {
+        let mut error = ValidationError::new("sku");
+        error.message = Some("SKU must be between 1 and 64 characters without spaces".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+fn validate_options(options: &BTreeMap<String, String>) -> Result<(), ValidationError> {
+    if options.len() > MAX_OPTIONS {
+        let mut error = ValidationError::new("options");
+        error.message = Some(format!("At most {} options are allowed", MAX_OPTIONS).into());
+        return Err(error);
+    }
+    let within = |text: &str, max: usize| !text.trim().is_empty() && text.chars().count() <= max;
+    if !options
+        .iter()
+        .all(|(name, value)| within(name, 50) && within(value, 100))
+    {
+        let mut error = ValidationError::new("options");
+        error.message =
+            Some("Option names must be 1 to 50 and values 1 to 100 characters long".into());
+        return Err(error);
+    }
+    Ok(())
+}
+
+// Create or update variant model. `options` maps an option to its value,
+// e.g. {"size": "M", "colour": "red"}, and a missing price inherits the
+// price of the product.
+#[derive(Deserialize, Validate)]
+pub struct NewVariant {
+    #[validate(custom = "validate_sku")]
+    pub sku: String,
+    #[serde(default)]
+    #[validate(custom = "validate_options")]
+    pub options: BTreeMap<String, String>,
+    #[validate(custom = "validate_price")]
+    pub price: Option<Money>,
+    // booked through the ledger, left as is on update when missing
+    #[validate(range(min = 0.0, message = "Stock must not be negative"))]
+    pub stock: Option<f64>,
+}
+
+// Columns written when creating or updating a variant
+#[derive(Insertable, AsChangeset)]
+#[table_name = "product_variants"]
+#[changeset_options(treat_none_as_null = "true")]
+struct VariantChangeset<'a> {
+    sku: &'a str,
+    options: serde_json::Value,
+    price: Option<i32>,
+    currency: Option<&'a str>,
+}
+
+impl<'a> From<&'a NewVariant> for VariantChangeset<'a> {
+    fn from(new_variant: &'a NewVariant) -> Self {
+        VariantChangeset {
+            sku: &new_variant.sku,
+            options: serde_json::json!(new_variant.options),
+            // validation keeps the amount within the range of the column
+            price: new_variant
+                .price
+                .as_ref()
+                .map(|price| price.amount_minor as i32),
+            currency: new_variant
+                .price
+                .as_ref()
+                .map(|price| price.currency.as_str()),
+        }
+    }
+}
+
+impl NewVariant {
+    pub fn create(
+        &self,
+        product_id: i32,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<ProductVariant, ApplicationError> {
+        conn.transaction(|| {
+            let product = lock_product(product_id, &actor.company, conn)?;
+            let has_variants = diesel::select(diesel::dsl::exists(
+                product_variants::table.filter(product_variants::product_id.eq(product_id)),
+            ))
+            .get_result::<bool>(conn)?;
+            // from the first variant on, the product only sums their stock
+            if !has_variants && product.stock != 0.0 {
+                return Err(ApplicationError::VariantConflict(
+                    "Bring the stock of the product to zero before adding its first variant"
+                        .to_string(),
+                ));
+            }
+
+            let variant = diesel::insert_into(product_variants::table)
+                .values((
+                    &VariantChangeset::from(self),
+                    product_variants::product_id.eq(product_id),
+                    product_variants::company.eq(&actor.company),
+                    product_variants::created_at.eq(Local::now().naive_local()),
+                ))
+                .get_result::<ProductVariant>(conn)
+                .map_err(variant_conflict)?;
+            match self.stock {
+                Some(initial) if initial != 0.0 => {
+                    StockMovement::apply_to(
+                        StockTarget::variant(product_id, variant.id),
+                        MovementKind::Adjustment,
+                        initial,
+                        "Initial stock",
+                        actor,
+                        conn,
+                    )?;
+                    Ok(ProductVariant::find(
+                        product_id,
+                        variant.id,
+                        &actor.company,
+                        conn,
+                    )?)
+                }
+                _ => Ok(variant),
+            }
+        })
+    }
+
+    // Replace the SKU, options and price of a variant. A stock change is
+    // recorded in the ledger as an adjustment.
+    pub fn update(
+        &self,
+        product_id: i32,
+        search_id: i32,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<ProductVariant, ApplicationError> {
+        conn.transaction(|| {
+            lock_product(product_id, &actor.company, conn)?;
+            let current = ProductVariant::find(product_id, search_id, &actor.company, conn)?;
+            if let Some(new_stock) = self.stock {
+                let delta = new_stock - current.stock;
+                let target = StockTarget::variant(product_id, current.id);
+                if delta < 0.0 {
+                    target.check_stock_edit(new_stock, conn)?;
+                }
+                if delta != 0.0 {
+                    StockMovement::apply_to(
+                        target,
+                        MovementKind::Adjustment,
+                        delta,
+                        "Variant update",
+                        actor,
+                        conn,
+                    )?;
+                }
+            }
+            diesel::update(product_variants::table.find(current.id))
+                .set(&VariantChangeset::from(self))
+                .get_result::<ProductVariant>(conn)
+                .map_err(variant_conflict)
+        })
+    }
+}
@@ -0,0 +1,149 @@
+use crate::diesel::ExpressionMethods;
+use crate::errors::application_error::ApplicationError;
+use crate::models::user::User;
+use crate::schema::refresh_tokens;
+use crate::schema::users;
+use crate::utils::token::{generate_token, hash_token};
+use chrono::{Duration, Local, NaiveDateTime};
+use diesel::Connection;
+use diesel::OptionalExtension;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use serde::Deserialize;
+
+// How long a refresh token stays valid
+pub const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+// Create a struct to represent a refresh token.
+#[derive(Queryable, Debug)]
+pub struct RefreshToken {
+    pub id: i32,
+    pub user_id: i32,
+    pub family_id: String,
+    pub token_hash: String,
+    pub expires_at: NaiveDateTime,
+    pub used_at: Option<NaiveDateTime>,
+    pub revoked_at: Option<NaiveDateTime>,
+    pub created_at: NaiveDateTime,
+}
+
+// Struct for inserting a new refresh token into database
+#[derive(Insertable, Debug)]
+#[table_name = "refresh_tokens"]
+struct NewRefreshToken<'a> {
+    user_id: i32,
+    family_id: &'a str,
+    token_hash: String,
+    expires_at: NaiveDateTime,
+    created_at: NaiveDateTime,
+}
+
+impl RefreshToken {
+    // Issue a refresh token for a new login, starting a new family.
+    // Returns the plain token.
+    pub fn issue(user: &User, conn: &PgConnection) -> Result<String, ApplicationError> {
+        Self::issue_in_family(user.id, &generate_token(), conn)
+    }
+
+    fn issue_in_family(
+        owner_id: i32,
+        family: &str,
+        conn: &PgConnection,
+    ) -> Result<String, ApplicationError> {
+        let token = generate_token();
+        let now = Local::now().naive_local();
+        diesel::insert_into(refresh_tokens::table)
+            .values(&NewRefreshToken {
+                user_id: owner_id,
+                family_id: family,
+                token_hash: hash_token(&token),
+                expires_at: now + Duration::days(REFRESH_TOKEN_TTL_DAYS),
+                created_at: now,
+            })
+            .execute(conn)?;
+        Ok(token)
+    }
+
+    // Exchange a refresh token for a new one of the same family. Presenting a
+    // token that was already rotated or revoked is treated as theft: the whole
+    // family is revoked.
+    pub fn rotate(token: &str, conn: &PgConnection) -> Result<(String, User), ApplicationError> {
+        let invalid = || ApplicationError::InvalidToken("Invalid or expired token".to_string());
+        let now = Local::now().naive_local();
+
+        let result = conn.transaction(|| {
+            let current = refresh_tokens::table
+                .filter(refresh_tokens::token_hash.eq(hash_token(token)))
+                .for_update()
+                .first::<RefreshToken>(conn)
+                .optional()?
+                .ok_or_else(invalid)?;
+
+            if current.used_at.is_some() || current.revoked_at.is_some() {
+                return Err(ApplicationError::TokenReused(current.family_id));
+            }
+            if current.expires_at <= now {
+                return Err(invalid());
+            }
+
+            diesel::update(refresh_tokens::table.find(current.id))
+                .set(refresh_tokens::used_at.eq(Some(now)))
+                .execute(conn)?;
+            let user = users::table.find(current.user_id).first::<User>(conn)?;
+            let rotated = Self::issue_in_family(current.user_id, &current.family_id, conn)?;
+            Ok((rotated, user))
+        });
+
+        match result {
+            // revoke outside of the failed transaction so it is not rolled back
+            Err(ApplicationError::TokenReused(family)) => {
+                Self::revoke_family(&family, conn)?;
+                Err(invalid())
+            }
+            result => result,
+        }
+    }
+
+    // Revoke the family a refresh token belongs to, used on logout
+    pub fn revoke(token: &str, conn: &PgConnection) -> Result<(), ApplicationError> {
+        let family = refresh_tokens::table
+            .filter(refresh_tokens::token_hash.eq(hash_token(token)))
+            .select(refresh_tokens::family_id)
+            .first::<String>(conn)
+            .optional()?;
+        if let Some(family) = family {
+            Self::revoke_family(&family, conn)?;
+        }
+        Ok(())
+    }
+
+    pub fn revoke_family(family: &str, conn: &PgConnection) -> Result<(), ApplicationError> {
+        diesel::update(
+            refresh_tokens::table
+                .filter(refresh_tokens::family_id.eq(family))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        )
+        .set(refresh_tokens::revoked_at.eq(Some(Local::now().naive_local())))
+        .execute(conn)?;
+        Ok(())
+    }
+
+    // Revoke every refresh token of a user, e.g. after a password reset
+    pub fn revoke_for_user(owner_id: i32, conn: &PgConnection) -> Result<(), ApplicationError> {
+        diesel::update(
+            refresh_tokens::table
+                .filter(refresh_tokens::user_id.eq(owner_id))
+                .filter(refresh_tokens::revoked_at.is_null()),
+        )
+        .set(refresh_tokens::revoked_at.eq(Some(Local::now().naive_local())))
+        .execute(conn)?;
+        Ok(())
+    }
+}
+
+// Refresh token model
+#[derive(Deserialize)]
+pub struct RefreshRequest {
+    pub refresh_token: String,
+}
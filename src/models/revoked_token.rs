@@ -0,0 +1,46 @@
+use crate::diesel::ExpressionMethods;
+use crate::errors::application_error::ApplicationError;
+use crate::schema::revoked_tokens;
+use chrono::{Local, TimeZone};
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+
+// Denylist of access tokens revoked before they expire, keyed by `jti`
+pub struct RevokedToken;
+
+impl RevokedToken {
+    // Revoke the access token `token_id` until `expires_at` (unix seconds)
+    pub fn revoke(
+        token_id: &str,
+        expires_at: usize,
+        conn: &PgConnection,
+    ) -> Result<(), ApplicationError> {
+        let now = Local::now().naive_local();
+        let expires_at = Local
+            .timestamp_opt(expires_at as i64, 0)
+            .single()
+            .map(|time| time.naive_local())
+            .unwrap_or(now);
+
+        // entries of expired tokens are useless, drop them on the way
+        diesel::delete(revoked_tokens::table.filter(revoked_tokens::expires_at.lt(now)))
+            .execute(conn)?;
+        diesel::insert_into(revoked_tokens::table)
+            .values((
+                revoked_tokens::jti.eq(token_id),
+                revoked_tokens::expires_at.eq(expires_at),
+            ))
+            .on_conflict_do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }
+
+    pub fn is_revoked(token_id: &str, conn: &PgConnection) -> Result<bool, ApplicationError> {
+        let revoked = diesel::select(diesel::dsl::exists(
+            revoked_tokens::table.filter(revoked_tokens::jti.eq(token_id)),
+        ))
+        .get_result::<bool>(conn)?;
+        Ok(revoked)
+    }
+}
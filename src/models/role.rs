@@ -0,0 +1,67 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+// Role of a user within their company, ordered from least to most privileged.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Editor,
+    Admin,
+    Owner,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Editor => "editor",
+            Role::Admin => "admin",
+            Role::Owner => "owner",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Role, String> {
+        match value {
+            "viewer" => Ok(Role::Viewer),
+            "editor" => Ok(Role::Editor),
+            "admin" => Ok(Role::Admin),
+            "owner" => Ok(Role::Owner),
+            _ => Err(format!("Unknown role {}", value)),
+        }
+    }
+}
+
+// Type-level roles used with the `RequireRole` extractor, e.g.
+// `RequireRole<Editor>` accepts editors, admins and owners.
+pub trait MinimumRole {
+    const ROLE: Role;
+}
+
+pub struct Editor;
+pub struct Admin;
+pub struct Owner;
+
+impl MinimumRole for Editor {
+    const ROLE: Role = Role::Editor;
+}
+
+impl MinimumRole for Admin {
+    const ROLE: Role = Role::Admin;
+}
+
+impl MinimumRole for Owner {
+    const ROLE: Role = Role::Owner;
+}
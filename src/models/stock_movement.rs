@@ -0,0 +1,449 @@
+use crate::diesel::{ExpressionMethods, OptionalExtension, PgExpressionMethods};
+use crate::errors::application_error::ApplicationError;
+use crate::schema::locations;
+use crate::schema::product_variants;
+use crate::schema::products;
+use crate::schema::stock_levels;
+use crate::schema::stock_movements;
+use crate::utils::jwt::SlimUser;
+use chrono::{Local, NaiveDateTime};
+use diesel::Connection;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+// The reason a product's stock moved.
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MovementKind {
+    Receipt,
+    Sale,
+    Adjustment,
+    Transfer,
+    Return,
+}
+
+impl MovementKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MovementKind::Receipt => "receipt",
+            MovementKind::Sale => "sale",
+            MovementKind::Adjustment => "adjustment",
+            MovementKind::Transfer => "transfer",
+            MovementKind::Return => "return",
+        }
+    }
+
+    // Turn the quantity sent by the client into a signed stock delta.
+    // Receipts and returns add stock, sales remove it, adjustments and
+    // transfers carry their own sign.
+    pub fn signed_quantity(&self, amount: f64) -> Result<f64, ApplicationError> {
+        if !amount.is_finite() || amount == 0.0 {
+            return Err(ApplicationError::InvalidMovement(
+                "Quantity must be a non-zero number".to_string(),
+            ));
+        }
+        match self {
+            MovementKind::Receipt | MovementKind::Return | MovementKind::Sale if amount < 0.0 => {
+                Err(ApplicationError::InvalidMovement(format!(
+                    "Quantity of a {} must be positive",
+                    self.as_str()
+                )))
+            }
+            MovementKind::Sale => Ok(-amount),
+            _ => Ok(amount),
+        }
+    }
+}
+
+// Create a struct to represent a stock movement.
+#[derive(Queryable, Serialize, Deserialize, Debug)]
+pub struct StockMovement {
+    pub id: i32,
+    pub product_id: i32,
+    pub kind: String,
+    pub quantity: f64,
+    pub reason: String,
+    pub user_email: String,
+    pub user_company: String,
+    pub created_at: NaiveDateTime,
+    // set when the movement booked the stock of a variant
+    pub variant_id: Option<i32>,
+    // set when the movement booked the stock held at a location
+    pub location_id: Option<i32>,
+}
+
+// Struct for inserting a new stock movement into database
+#[derive(Insertable, Debug)]
+#[table_name = "stock_movements"]
+struct NewStockMovement<'a> {
+    product_id: i32,
+    kind: &'a str,
+    quantity: f64,
+    reason: &'a str,
+    user_email: &'a str,
+    user_company: &'a str,
+    created_at: NaiveDateTime,
+    variant_id: Option<i32>,
+    location_id: Option<i32>,
+}
+
+// The stock a movement books: a product or one of its variants, either at a
+// location or not allocated to any
+#[derive(Clone, Copy, Debug)]
+pub struct StockTarget {
+    pub product_id: i32,
+    pub variant_id: Option<i32>,
+    pub location_id: Option<i32>,
+}
+
+impl StockTarget {
+    pub fn product(product_id: i32) -> Self {
+        StockTarget {
+            product_id,
+            variant_id: None,
+            location_id: None,
+        }
+    }
+
+    pub fn variant(product_id: i32, variant_id: i32) -> Self {
+        StockTarget {
+            variant_id: Some(variant_id),
+            ..StockTarget::product(product_id)
+        }
+    }
+
+    pub fn at(self, location_id: Option<i32>) -> Self {
+        StockTarget {
+            location_id,
+            ..self
+        }
+    }
+
+    // Stock of the product or variant held at any location
+    fn allocated(&self, conn: &PgConnection) -> Result<f64, diesel::result::Error> {
+        Ok(stock_levels::table
+            .filter(stock_levels::product_id.eq(self.product_id))
+            .filter(stock_levels::variant_id.is_not_distinct_from(self.variant_id))
+            .select(diesel::dsl::sum(stock_levels::quantity))
+            .first::<Option<f64>>(conn)?
+            .unwrap_or(0.0))
+    }
+
+    // An absolute stock edit only adds or removes unallocated stock, what is
+    // held at a location leaves through a movement or transfer naming it
+    pub fn check_stock_edit(
+        &self,
+        new_stock: f64,
+        conn: &PgConnection,
+    ) -> Result<(), ApplicationError> {
+        let allocated = self.allocated(conn)?;
+        if new_stock < allocated {
+            return Err(ApplicationError::InvalidMovement(format!(
+                "{} of the stock is held at locations, reduce it with a movement at \
+                 /products/{}/movements giving the location_id, or move it with \
+                 /products/{}/transfers",
+                allocated, self.product_id, self.product_id
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Record movement model
+#[derive(Deserialize, Validate)]
+pub struct RecordMovement {
+    pub kind: MovementKind,
+    pub quantity: f64,
+    #[validate(length(
+        min = 1,
+        max = 255,
+        message = "Reason must be between 1 and 255 characters long"
+    ))]
+    pub reason: String,
+    // required for a product with variants, whose stock they hold
+    pub variant_id: Option<i32>,
+    // the unallocated stock moves when missing
+    pub location_id: Option<i32>,
+}
+
+fn check_reason(reason: &str) -> Result<(), ApplicationError> {
+    if reason.trim().is_empty() {
+        return Err(ApplicationError::InvalidMovement(
+            "Reason must not be empty".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl RecordMovement {
+    // Apply the movement to the product's stock and append it to the ledger.
+    pub fn record(
+        &self,
+        search_id: i32,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<StockMovement, ApplicationError> {
+        let delta = self.kind.signed_quantity(self.quantity)?;
+        check_reason(&self.reason)?;
+        let target = StockTarget {
+            product_id: search_id,
+            variant_id: self.variant_id,
+            location_id: self.location_id,
+        };
+        conn.transaction(|| {
+            StockMovement::apply_to(target, self.kind, delta, &self.reason, actor, conn)
+        })
+    }
+}
+
+// Stock transfer model, a missing location stands for the unallocated stock
+#[derive(Deserialize, Validate)]
+pub struct StockTransfer {
+    pub from_location_id: Option<i32>,
+    pub to_location_id: Option<i32>,
+    pub variant_id: Option<i32>,
+    pub quantity: f64,
+    #[validate(length(
+        min = 1,
+        max = 255,
+        message = "Reason must be between 1 and 255 characters long"
+    ))]
+    pub reason: String,
+}
+
+impl StockTransfer {
+    // Move stock between two locations of the product, recorded as a
+    // transfer out of one and a transfer into the other. Both happen or
+    // neither does.
+    pub fn execute(
+        &self,
+        search_id: i32,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<Vec<StockMovement>, ApplicationError> {
+        if !self.quantity.is_finite() || self.quantity <= 0.0 {
+            return Err(ApplicationError::InvalidMovement(
+                "Quantity must be a positive number".to_string(),
+            ));
+        }
+        if self.from_location_id == self.to_location_id {
+            return Err(ApplicationError::InvalidMovement(
+                "Stock must be transferred between two different locations".to_string(),
+            ));
+        }
+        check_reason(&self.reason)?;
+        let target = StockTarget {
+            product_id: search_id,
+            variant_id: self.variant_id,
+            location_id: None,
+        };
+        conn.transaction(|| {
+            let out = StockMovement::apply_to(
+                target.at(self.from_location_id),
+                MovementKind::Transfer,
+                -self.quantity,
+                &self.reason,
+                actor,
+                conn,
+            )?;
+            let into = StockMovement::apply_to(
+                target.at(self.to_location_id),
+                MovementKind::Transfer,
+                self.quantity,
+                &self.reason,
+                actor,
+                conn,
+            )?;
+            Ok(vec![out, into])
+        })
+    }
+}
+
+impl StockMovement {
+    // Move stock by `delta` and write the ledger entry. Callers must already
+    // be inside a transaction.
+    pub fn apply(
+        search_id: i32,
+        movement_kind: MovementKind,
+        delta: f64,
+        movement_reason: &str,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<StockMovement, ApplicationError> {
+        StockMovement::apply_to(
+            StockTarget::product(search_id),
+            movement_kind,
+            delta,
+            movement_reason,
+            actor,
+            conn,
+        )
+    }
+
+    // Like `apply` for the stock of a variant or of a location. The variant
+    // keeps the sum of its locations and unallocated stock, the product the
+    // sum of its variants.
+    pub fn apply_to(
+        target: StockTarget,
+        movement_kind: MovementKind,
+        delta: f64,
+        movement_reason: &str,
+        actor: &SlimUser,
+        conn: &PgConnection,
+    ) -> Result<StockMovement, ApplicationError> {
+        let search_id = target.product_id;
+        // lock the product row so concurrent movements serialize, including
+        // those of different variants and locations
+        let current_stock = products::table
+            .find(search_id)
+            .filter(products::company.eq(&actor.company))
+            .select(products::stock)
+            .for_update()
+            .first::<f64>(conn)?;
+
+        let target_stock = match target.variant_id {
+            Some(variant_id) => product_variants::table
+                .find(variant_id)
+                .filter(product_variants::product_id.eq(search_id))
+                .select(product_variants::stock)
+                .for_update()
+                .first::<f64>(conn)
+                .map_err(|err| match err {
+                    diesel::result::Error::NotFound => ApplicationError::InvalidMovement(format!(
+                        "Variant {} not found",
+                        variant_id
+                    )),
+                    _ => ApplicationError::DBError(err),
+                })?,
+            None => {
+                let has_variants = diesel::select(diesel::dsl::exists(
+                    product_variants::table.filter(product_variants::product_id.eq(search_id)),
+                ))
+                .get_result::<bool>(conn)?;
+                if has_variants {
+                    return Err(ApplicationError::InvalidMovement(
+                        "The stock of this product is held by its variants, give a variant_id"
+                            .to_string(),
+                    ));
+                }
+                current_stock
+            }
+        };
+
+        let levels = stock_levels::table
+            .filter(stock_levels::product_id.eq(search_id))
+            .filter(stock_levels::variant_id.is_not_distinct_from(target.variant_id))
+            .into_boxed();
+        let available = match target.location_id {
+            Some(location_id) => {
+                let known = diesel::select(diesel::dsl::exists(
+                    locations::table
+                        .find(location_id)
+                        .filter(locations::company.eq(&actor.company)),
+                ))
+                .get_result::<bool>(conn)?;
+                if !known {
+                    return Err(ApplicationError::InvalidMovement(format!(
+                        "Location {} not found",
+                        location_id
+                    )));
+                }
+                levels
+                    .filter(stock_levels::location_id.eq(location_id))
+                    .select(stock_levels::quantity)
+                    .first::<f64>(conn)
+                    .optional()?
+                    .unwrap_or(0.0)
+            }
+            // what is held at no location
+            None => (target_stock - target.allocated(conn)?).max(0.0),
+        };
+
+        if available + delta < 0.0 {
+            return Err(ApplicationError::InsufficientStock(format!(
+                "Insufficient stock: {} available",
+                available
+            )));
+        }
+
+        if let Some(location_id) = target.location_id {
+            let updated = diesel::update(
+                stock_levels::table
+                    .filter(stock_levels::product_id.eq(search_id))
+                    .filter(stock_levels::variant_id.is_not_distinct_from(target.variant_id))
+                    .filter(stock_levels::location_id.eq(location_id)),
+            )
+            .set(stock_levels::quantity.eq(available + delta))
+            .execute(conn)?;
+            if updated == 0 {
+                diesel::insert_into(stock_levels::table)
+                    .values((
+                        stock_levels::product_id.eq(search_id),
+                        stock_levels::variant_id.eq(target.variant_id),
+                        stock_levels::location_id.eq(location_id),
+                        stock_levels::quantity.eq(available + delta),
+                    ))
+                    .execute(conn)?;
+            }
+        }
+
+        let product_stock = match target.variant_id {
+            Some(variant_id) => {
+                diesel::update(product_variants::table.find(variant_id))
+                    .set(product_variants::stock.eq(target_stock + delta))
+                    .execute(conn)?;
+                // summed again rather than adding delta, so rounding cannot drift
+                product_variants::table
+                    .filter(product_variants::product_id.eq(search_id))
+                    .select(diesel::dsl::sum(product_variants::stock))
+                    .first::<Option<f64>>(conn)?
+                    .unwrap_or(0.0)
+            }
+            None => current_stock + delta,
+        };
+        diesel::update(products::table.find(search_id))
+            .set(products::stock.eq(product_stock))
+            .execute(conn)?;
+
+        let movement = NewStockMovement {
+            product_id: search_id,
+            kind: movement_kind.as_str(),
+            quantity: delta,
+            reason: movement_reason,
+            user_email: &actor.email,
+            user_company: &actor.company,
+            created_at: Local::now().naive_local(),
+            variant_id: target.variant_id,
+            location_id: target.location_id,
+        };
+        let created = diesel::insert_into(stock_movements::table)
+            .values(&movement)
+            .get_result(conn)?;
+        Ok(created)
+    }
+
+    // List the movement history of a product, newest first
+    pub fn for_product(
+        search_id: i32,
+        owner: &str,
+        conn: &PgConnection,
+    ) -> Result<Vec<StockMovement>, ApplicationError> {
+        // make sure the product exists so a missing product is not an empty list
+        products::table
+            .find(search_id)
+            .filter(products::company.eq(owner))
+            .select(products::id)
+            .first::<i32>(conn)?;
+        let movements = stock_movements::table
+            .filter(stock_movements::product_id.eq(search_id))
+            .order((
+                stock_movements::created_at.desc(),
+                stock_movements::id.desc(),
+            ))
+            .load::<StockMovement>(conn)?;
+        Ok(movements)
+    }
+}
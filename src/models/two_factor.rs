@@ -0,0 +1,241 @@
+use crate::diesel::ExpressionMethods;
+use crate::errors::application_error::ApplicationError;
+use crate::models::user::User;
+use crate::schema::recovery_codes;
+use crate::schema::users;
+use crate::utils::token::hash_token;
+use crate::utils::totp::{generate_secret, provisioning_uri, verify_code};
+use chrono::{Local, NaiveDateTime};
+use data_encoding::BASE32_NOPAD;
+use diesel::Connection;
+use diesel::OptionalExtension;
+use diesel::PgConnection;
+use diesel::QueryDsl;
+use diesel::RunQueryDsl;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+// Number of recovery codes handed out when two-factor authentication is enabled
+pub const RECOVERY_CODE_COUNT: usize = 10;
+
+// Struct for inserting a recovery code into database
+#[derive(Insertable, Debug)]
+#[table_name = "recovery_codes"]
+struct NewRecoveryCode {
+    user_id: i32,
+    code_hash: String,
+    created_at: NaiveDateTime,
+}
+
+// Secret and uri returned when enrolment starts
+#[derive(Serialize)]
+pub struct Enrollment {
+    pub secret: String,
+    pub provisioning_uri: String,
+}
+
+// A code from the authenticator app, or a recovery code
+#[derive(Deserialize)]
+pub struct TwoFactorCode {
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+// Recovery codes are compared without separators and case
+fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+// Generate a recovery code such as `ABCD-EFGH-IJKL-MNOP`
+fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 10];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    let code = BASE32_NOPAD.encode(&bytes);
+    code.as_bytes()
+        .chunks(4)
+        .map(|chunk| String::from_utf8_lossy(chunk).into_owned())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn invalid_code() -> ApplicationError {
+    ApplicationError::InvalidTwoFactorCode("Invalid two-factor code".to_string())
+}
+
+fn find_user(user_email: &str, conn: &PgConnection) -> Result<User, ApplicationError> {
+    Ok(users::table
+        .filter(users::email.eq(user_email))
+        .for_update()
+        .first::<User>(conn)?)
+}
+
+// Accept the next unused code of the authenticator app and remember its step
+fn accept_totp(user: &User, code: &str, conn: &PgConnection) -> Result<(), ApplicationError> {
+    let secret = user.totp_secret.as_deref().ok_or_else(invalid_code)?;
+    let now = Local::now().timestamp();
+    let step = verify_code(secret, code, now, user.totp_last_step).ok_or_else(invalid_code)?;
+    diesel::update(users::table.find(user.id))
+        .set(users::totp_last_step.eq(Some(step)))
+        .execute(conn)?;
+    Ok(())
+}
+
+impl User {
+    // Start enrolment with a new pending secret, replacing any earlier pending one
+    pub fn enroll_two_factor(
+        user_email: &str,
+        conn: &PgConnection,
+    ) -> Result<Enrollment, ApplicationError> {
+        conn.transaction(|| {
+            let user = find_user(user_email, conn)?;
+            if user.two_factor_enabled() {
+                return Err(ApplicationError::TwoFactorEnabled(
+                    "Two-factor authentication is already enabled".to_string(),
+                ));
+            }
+            let secret = generate_secret();
+            diesel::update(users::table.find(user.id))
+                .set((
+                    users::totp_secret.eq(Some(secret.as_str())),
+                    users::totp_last_step.eq(None::<i64>),
+                ))
+                .execute(conn)?;
+            Ok(Enrollment {
+                provisioning_uri: provisioning_uri(&user.email, &secret),
+                secret,
+            })
+        })
+    }
+
+    // Enable two-factor authentication once the user proves the app is set up.
+    // Returns the plain recovery codes, only their hashes are stored.
+    pub fn confirm_two_factor(
+        user_email: &str,
+        code: &str,
+        conn: &PgConnection,
+    ) -> Result<Vec<String>, ApplicationError> {
+        conn.transaction(|| {
+            let user = find_user(user_email, conn)?;
+            if user.two_factor_enabled() {
+                return Err(ApplicationError::TwoFactorEnabled(
+                    "Two-factor authentication is already enabled".to_string(),
+                ));
+            }
+            accept_totp(&user, code, conn)?;
+
+            let now = Local::now().naive_local();
+            diesel::update(users::table.find(user.id))
+                .set(users::totp_enabled_at.eq(Some(now)))
+                .execute(conn)?;
+            diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user.id)))
+                .execute(conn)?;
+            let codes = (0..RECOVERY_CODE_COUNT)
+                .map(|_| generate_recovery_code())
+                .collect::<Vec<_>>();
+            let rows = codes
+                .iter()
+                .map(|code| NewRecoveryCode {
+                    user_id: user.id,
+                    code_hash: hash_token(&normalize_recovery_code(code)),
+                    created_at: now,
+                })
+                .collect::<Vec<_>>();
+            diesel::insert_into(recovery_codes::table)
+                .values(&rows)
+                .execute(conn)?;
+            Ok(codes)
+        })
+    }
+
+    // Check the second factor of a login, a recovery code is used up
+    pub fn verify_two_factor(
+        user_email: &str,
+        two_factor_code: &TwoFactorCode,
+        conn: &PgConnection,
+    ) -> Result<User, ApplicationError> {
+        conn.transaction(|| {
+            let user = find_user(user_email, conn)?;
+            if !user.two_factor_enabled() {
+                return Err(invalid_code());
+            }
+            match (&two_factor_code.code, &two_factor_code.recovery_code) {
+                (Some(code), _) => accept_totp(&user, code, conn)?,
+                (None, Some(recovery_code)) => {
+                    let code_id = recovery_codes::table
+                        .filter(recovery_codes::user_id.eq(user.id))
+                        .filter(
+                            recovery_codes::code_hash
+                                .eq(hash_token(&normalize_recovery_code(recovery_code))),
+                        )
+                        .filter(recovery_codes::used_at.is_null())
+                        .select(recovery_codes::id)
+                        .for_update()
+                        .first::<i32>(conn)
+                        .optional()?
+                        .ok_or_else(invalid_code)?;
+                    diesel::update(recovery_codes::table.find(code_id))
+                        .set(recovery_codes::used_at.eq(Some(Local::now().naive_local())))
+                        .execute(conn)?;
+                }
+                (None, None) => return Err(invalid_code()),
+            }
+            Ok(user)
+        })
+    }
+
+    // Turn two-factor authentication off, a valid second factor is required
+    pub fn disable_two_factor(
+        user_email: &str,
+        two_factor_code: &TwoFactorCode,
+        conn: &PgConnection,
+    ) -> Result<(), ApplicationError> {
+        conn.transaction(|| {
+            let user = Self::verify_two_factor(user_email, two_factor_code, conn)?;
+            diesel::update(users::table.find(user.id))
+                .set((
+                    users::totp_secret.eq(None::<String>),
+                    users::totp_enabled_at.eq(None::<NaiveDateTime>),
+                    users::totp_last_step.eq(None::<i64>),
+                ))
+                .execute(conn)?;
+            diesel::delete(recovery_codes::table.filter(recovery_codes::user_id.eq(user.id)))
+                .execute(conn)?;
+            Ok(())
+        })
+    }
+}
+
+// Second step of a login for users with two-factor authentication
+#[derive(Deserialize)]
+pub struct TwoFactorLogin {
+    pub two_factor_token: String,
+    #[serde(flatten)]
+    pub code: TwoFactorCode,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn generates_four_blocks_of_four_characters() {
+        let code = generate_recovery_code();
+        let blocks: Vec<&str> = code.split('-').collect();
+        assert_eq!(blocks.len(), 4, "{}", code);
+        assert!(blocks.iter().all(|block| block.len() == 4), "{}", code);
+        assert!(BASE32_NOPAD
+            .decode(normalize_recovery_code(&code).as_bytes())
+            .is_ok());
+    }
+
+    #[test]
+    fn compares_recovery_codes_without_separators_and_case() {
+        assert_eq!(
+            normalize_recovery_code(" abcd-EFGH ijkl_mnop "),
+            "ABCDEFGHIJKLMNOP"
+        );
+    }
+}
@@ -1,9 +1,12 @@
 use crate::diesel::ExpressionMethods;
 use crate::errors::application_error::ApplicationError;
+use crate::models::refresh_token::RefreshToken;
+use crate::models::role::Role;
 use crate::schema::users;
-use crate::schema::users::dsl::*;
+use crate::utils::validation::validate_password_strength;
 use chrono::NaiveDateTime;
 use serde::{Deserialize, Serialize};
+use validator::Validate;
 
 // Create a struct to represent a user.
 #[derive(Queryable, Serialize, Deserialize, Insertable, Debug)]
@@ -16,33 +19,273 @@ pub struct User {
     #[serde(skip)]
     pub password: String,
     pub created_at: NaiveDateTime,
+    #[serde(skip)]
+    pub email_verified_at: Option<NaiveDateTime>,
+    #[serde(skip)]
+    pub sessions_invalidated_at: Option<NaiveDateTime>,
+    pub role: String,
+    #[serde(skip)]
+    pub totp_secret: Option<String>,
+    #[serde(skip)]
+    pub totp_enabled_at: Option<NaiveDateTime>,
+    #[serde(skip)]
+    pub totp_last_step: Option<i64>,
+    #[serde(skip)]
+    pub disabled_at: Option<NaiveDateTime>,
 }
 
 use bcrypt::{hash, verify, DEFAULT_COST};
-use chrono::Local;
-use diesel::PgConnection;
+use chrono::{Local, TimeZone, Timelike};
+use diesel::result::DatabaseErrorKind;
+use diesel::sql_types::Text;
 use diesel::RunQueryDsl;
+use diesel::{Connection, OptionalExtension, PgConnection};
 
 impl User {
+    pub fn two_factor_enabled(&self) -> bool {
+        self.totp_enabled_at.is_some()
+    }
+
+    // Parsed role of the user, unknown values are treated as the least privileged role
+    pub fn role(&self) -> Role {
+        self.role.parse().unwrap_or(Role::Viewer)
+    }
+
     pub fn hash_password(plain_password: &str) -> Result<String, ApplicationError> {
-        hash(plain_password, DEFAULT_COST).map_err(|err| ApplicationError::HashError(err))
+        hash(plain_password, DEFAULT_COST).map_err(ApplicationError::HashError)
+    }
+
+    pub fn find_by_email(user_email: &str, conn: &PgConnection) -> Result<User, ApplicationError> {
+        Ok(users::table
+            .filter(users::email.eq(user_email))
+            .first::<User>(conn)?)
+    }
+
+    // User creation within a company runs one at a time, so two users
+    // cannot both find the company new and both become its owner
+    fn lock_company(user_company: &str, conn: &PgConnection) -> Result<(), ApplicationError> {
+        diesel::sql_query("SELECT pg_advisory_xact_lock(hashtext($1))")
+            .bind::<Text, _>(format!("company:{}", user_company))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    fn company_exists(user_company: &str, conn: &PgConnection) -> Result<bool, ApplicationError> {
+        Ok(diesel::select(diesel::dsl::exists(
+            users::table.filter(users::company.eq(user_company)),
+        ))
+        .get_result::<bool>(conn)?)
     }
 
+    // Registration founds a new company owned by the user. Joining an
+    // existing company takes an account created by an administrator.
     pub fn create(
         register_user: &RegisterUser,
         conn: &PgConnection,
     ) -> Result<User, ApplicationError> {
         let hashed_password = Self::hash_password(&register_user.password)?;
+        conn.transaction(|| {
+            Self::lock_company(&register_user.company, conn)?;
+            if Self::company_exists(&register_user.company, conn)? {
+                return Err(ApplicationError::CompanyTaken(
+                    "Company is already registered, ask an administrator for an account"
+                        .to_string(),
+                ));
+            }
+            Self::insert(register_user, hashed_password, Role::Owner, None, conn)
+        })
+    }
+
+    // Create a user with the given role, by default owner of a new company
+    // and viewer of an existing one. Already verified when `verified_at` is
+    // set, used by the admin cli which vouches for the email itself.
+    pub fn create_with_role(
+        register_user: &RegisterUser,
+        user_role: Option<Role>,
+        verified_at: Option<NaiveDateTime>,
+        conn: &PgConnection,
+    ) -> Result<User, ApplicationError> {
+        let hashed_password = Self::hash_password(&register_user.password)?;
+        conn.transaction(|| {
+            Self::lock_company(&register_user.company, conn)?;
+            let user_role = match user_role {
+                Some(user_role) => user_role,
+                None if Self::company_exists(&register_user.company, conn)? => Role::Viewer,
+                None => Role::Owner,
+            };
+            Self::insert(register_user, hashed_password, user_role, verified_at, conn)
+        })
+    }
+
+    fn insert(
+        register_user: &RegisterUser,
+        hashed_password: String,
+        user_role: Role,
+        verified_at: Option<NaiveDateTime>,
+        conn: &PgConnection,
+    ) -> Result<User, ApplicationError> {
         let user = NewUser {
             email: register_user.email.to_string(),
             company: register_user.company.to_string(),
             password: hashed_password,
             created_at: Local::now().naive_local(),
+            role: user_role.to_string(),
+            email_verified_at: verified_at,
         };
         diesel::insert_into(users::table)
             .values(&user)
             .get_result(conn)
-            .map_err(|_| ApplicationError::DBError(diesel::result::Error::NotFound))
+            .map_err(|err| match err {
+                diesel::result::Error::DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    ApplicationError::EmailTaken("Email is already registered".to_string())
+                }
+                _ => ApplicationError::DBError(err),
+            })
+    }
+
+    // Replace the password of a user, ending every session and refresh token
+    // issued before now. Runs inside the caller's transaction when there is one.
+    pub fn replace_password(
+        user_id: i32,
+        hashed_password: &str,
+        conn: &PgConnection,
+    ) -> Result<User, ApplicationError> {
+        conn.transaction(|| {
+            RefreshToken::revoke_for_user(user_id, conn)?;
+            let user = diesel::update(users::table.find(user_id))
+                .set((
+                    users::password.eq(hashed_password),
+                    users::sessions_invalidated_at.eq(Some(Local::now().naive_local())),
+                ))
+                .get_result::<User>(conn)?;
+            Ok(user)
+        })
+    }
+
+    // Set a new password for the user with the given email
+    pub fn set_password(
+        user_email: &str,
+        plain_password: &str,
+        conn: &PgConnection,
+    ) -> Result<User, ApplicationError> {
+        let hashed_password = Self::hash_password(plain_password)?;
+        let user = Self::find_by_email(user_email, conn)?;
+        Self::replace_password(user.id, &hashed_password, conn)
+    }
+
+    // Change the role of the user with the given email and end their
+    // sessions, which still carry the old role. A company keeps at least one
+    // owner, so its last owner cannot be given another role.
+    pub fn set_role(
+        user_email: &str,
+        user_role: Role,
+        conn: &PgConnection,
+    ) -> Result<User, ApplicationError> {
+        conn.transaction(|| {
+            let user = Self::find_by_email(user_email, conn)?;
+            Self::lock_company(&user.company, conn)?;
+            if user.role() == Role::Owner && user_role != Role::Owner {
+                let owners = users::table
+                    .filter(users::company.eq(&user.company))
+                    .filter(users::role.eq(Role::Owner.as_str()))
+                    .count()
+                    .get_result::<i64>(conn)?;
+                if owners <= 1 {
+                    return Err(ApplicationError::LastOwner(
+                        "The last owner of a company cannot be given another role".to_string(),
+                    ));
+                }
+            }
+            RefreshToken::revoke_for_user(user.id, conn)?;
+            let user = diesel::update(users::table.find(user.id))
+                .set((
+                    users::role.eq(user_role.to_string()),
+                    users::sessions_invalidated_at.eq(Some(Local::now().naive_local())),
+                ))
+                .get_result::<User>(conn)?;
+            Ok(user)
+        })
+    }
+
+    // Stop the user from signing in and end their sessions, their API keys
+    // are refused until the user is enabled again. Disabling an already
+    // disabled user keeps the original date.
+    pub fn disable(user_email: &str, conn: &PgConnection) -> Result<User, ApplicationError> {
+        conn.transaction(|| {
+            let user = Self::find_by_email(user_email, conn)?;
+            if user.disabled_at.is_some() {
+                return Ok(user);
+            }
+            let now = Local::now().naive_local();
+            RefreshToken::revoke_for_user(user.id, conn)?;
+            let user = diesel::update(users::table.find(user.id))
+                .set((
+                    users::disabled_at.eq(Some(now)),
+                    users::sessions_invalidated_at.eq(Some(now)),
+                ))
+                .get_result::<User>(conn)?;
+            Ok(user)
+        })
+    }
+
+    // Allow a disabled user to sign in again
+    pub fn enable(user_email: &str, conn: &PgConnection) -> Result<User, ApplicationError> {
+        let user = diesel::update(users::table.filter(users::email.eq(user_email)))
+            .set(users::disabled_at.eq(None::<NaiveDateTime>))
+            .get_result::<User>(conn)?;
+        Ok(user)
+    }
+
+    // Whether a session token issued at `issued_at` (unix seconds) is still
+    // accepted for the user, i.e. the user is not disabled and the token was
+    // not issued before a password reset
+    pub fn session_is_valid(
+        user_email: &str,
+        issued_at: usize,
+        conn: &PgConnection,
+    ) -> Result<bool, ApplicationError> {
+        let (invalidated_at, disabled_at) = users::table
+            .filter(users::email.eq(user_email))
+            .select((users::sessions_invalidated_at, users::disabled_at))
+            .first::<(Option<NaiveDateTime>, Option<NaiveDateTime>)>(conn)?;
+        if disabled_at.is_some() {
+            return Ok(false);
+        }
+        let issued_at = Local
+            .timestamp_opt(issued_at as i64, 0)
+            .single()
+            .map(|time| time.naive_local());
+        Ok(match (invalidated_at, issued_at) {
+            (Some(invalidated_at), Some(issued_at)) => {
+                issued_at >= invalidated_at.with_nanosecond(0).unwrap_or(invalidated_at)
+            }
+            (Some(_), None) => false,
+            (None, _) => true,
+        })
+    }
+
+    // An enabled user who still has to verify their email, if there is one
+    pub fn find_unverified(
+        user_email: &str,
+        conn: &PgConnection,
+    ) -> Result<Option<User>, ApplicationError> {
+        Ok(users::table
+            .filter(users::email.eq(user_email))
+            .filter(users::email_verified_at.is_null())
+            .filter(users::disabled_at.is_null())
+            .first::<User>(conn)
+            .optional()?)
+    }
+
+    // Mark the email of a user as verified
+    pub fn verify_email(
+        verified_email: &str,
+        conn: &PgConnection,
+    ) -> Result<User, ApplicationError> {
+        let user = diesel::update(users::table.filter(users::email.eq(verified_email)))
+            .set(users::email_verified_at.eq(Some(Local::now().naive_local())))
+            .get_result(conn)?;
+        Ok(user)
     }
 }
 
@@ -54,27 +297,37 @@ pub struct NewUser {
     pub company: String,
     pub password: String,
     pub created_at: NaiveDateTime,
+    pub role: String,
+    pub email_verified_at: Option<NaiveDateTime>,
 }
 
 // Register user model
-#[derive(Deserialize)]
+#[derive(Deserialize, Validate)]
 pub struct RegisterUser {
+    #[validate(
+        email(message = "Email must be a valid email address"),
+        length(max = 100, message = "Email must be at most 100 characters long")
+    )]
     pub email: String,
+    #[validate(length(
+        min = 1,
+        max = 100,
+        message = "Company must be between 1 and 100 characters long"
+    ))]
     pub company: String,
+    #[validate(
+        custom = "validate_password_strength",
+        must_match(
+            other = "password_confirmation",
+            message = "Password and password confirmation do not match"
+        )
+    )]
     pub password: String,
     pub password_confirmation: String,
 }
 
-impl RegisterUser {
-    pub fn validate(self) -> Result<RegisterUser, ApplicationError> {
-        if self.password != self.password_confirmation {
-            return Err(ApplicationError::PasswordNotMatch(
-                "Password and password confirmation do not match".to_string(),
-            ));
-        }
-        Ok(self)
-    }
-}
+// bcrypt hash with DEFAULT_COST verified when the email is unknown
+const DUMMY_PASSWORD_HASH: &str = "$2b$12$pfQKQSNHcdNkZUd4vJnbLu1QkJmH05gVOMuo75Hvo9Ld6bn3hLfK6";
 
 // Authenticate user model
 #[derive(Deserialize)]
@@ -82,28 +335,44 @@ pub struct AuthenticateUser {
     pub email: String,
     pub password: String,
 }
-use crate::schema::users::dsl::email;
 use diesel::QueryDsl;
 
 impl AuthenticateUser {
     // login
     pub fn login(&self, conn: &PgConnection) -> Result<User, ApplicationError> {
         // find records with the same email
-        let mut records = users.filter(email.eq(&self.email)).load::<User>(conn)?;
+        let mut records = users::table
+            .filter(users::email.eq(&self.email))
+            .load::<User>(conn)?;
 
-        // Get the user from the records
-        let user = records.pop().ok_or(ApplicationError::WrongPassword(
-            "Email or password is incorrect".to_string(),
-        ))?;
+        // Get the user from the records. Unknown emails still pay for a
+        // bcrypt verification so response times do not reveal which exist.
+        let user = match records.pop() {
+            Some(user) => user,
+            None => {
+                let _ = verify(&self.password, DUMMY_PASSWORD_HASH);
+                return Err(ApplicationError::WrongPassword(
+                    "Email or password is incorrect".to_string(),
+                ));
+            }
+        };
         // Verify the password
         let password_is_valid =
-            verify(&self.password, &user.password).map_err(|e| ApplicationError::HashError(e))?;
-        if password_is_valid {
-            Ok(user)
-        } else {
+            verify(&self.password, &user.password).map_err(ApplicationError::HashError)?;
+        if !password_is_valid {
             Err(ApplicationError::WrongPassword(
                 "Email or password is incorrect".to_string(),
             ))
+        } else if user.email_verified_at.is_none() {
+            Err(ApplicationError::EmailNotVerified(
+                "Email address has not been verified".to_string(),
+            ))
+        } else if user.disabled_at.is_some() {
+            Err(ApplicationError::AccountDisabled(
+                "This account has been disabled".to_string(),
+            ))
+        } else {
+            Ok(user)
         }
     }
 }
@@ -1,9 +1,145 @@
+table! {
+    api_keys (id) {
+        id -> Int4,
+        company -> Varchar,
+        name -> Varchar,
+        prefix -> Varchar,
+        key_hash -> Varchar,
+        scopes -> Array<Text>,
+        created_by -> Varchar,
+        expires_at -> Nullable<Timestamp>,
+        last_used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    categories (id) {
+        id -> Int4,
+        company -> Varchar,
+        parent_id -> Nullable<Int4>,
+        name -> Varchar,
+        path -> Text,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    locations (id) {
+        id -> Int4,
+        company -> Varchar,
+        name -> Varchar,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    login_attempts (id) {
+        id -> Int4,
+        email -> Varchar,
+        ip_address -> Varchar,
+        succeeded -> Bool,
+        reason -> Nullable<Varchar>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    password_reset_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        token_hash -> Varchar,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    product_categories (product_id, category_id) {
+        product_id -> Int4,
+        category_id -> Int4,
+    }
+}
+
+table! {
+    product_variants (id) {
+        id -> Int4,
+        product_id -> Int4,
+        company -> Varchar,
+        sku -> Varchar,
+        options -> Jsonb,
+        price -> Nullable<Int4>,
+        currency -> Nullable<Varchar>,
+        stock -> Float8,
+        created_at -> Timestamp,
+    }
+}
+
 table! {
     products (id) {
         id -> Int4,
         name -> Varchar,
         stock -> Float8,
         price -> Nullable<Int4>,
+        company -> Varchar,
+        currency -> Varchar,
+    }
+}
+
+table! {
+    recovery_codes (id) {
+        id -> Int4,
+        user_id -> Int4,
+        code_hash -> Varchar,
+        used_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    refresh_tokens (id) {
+        id -> Int4,
+        user_id -> Int4,
+        family_id -> Varchar,
+        token_hash -> Varchar,
+        expires_at -> Timestamp,
+        used_at -> Nullable<Timestamp>,
+        revoked_at -> Nullable<Timestamp>,
+        created_at -> Timestamp,
+    }
+}
+
+table! {
+    revoked_tokens (jti) {
+        jti -> Varchar,
+        expires_at -> Timestamp,
+    }
+}
+
+table! {
+    stock_levels (id) {
+        id -> Int4,
+        product_id -> Int4,
+        variant_id -> Nullable<Int4>,
+        location_id -> Int4,
+        quantity -> Float8,
+    }
+}
+
+table! {
+    stock_movements (id) {
+        id -> Int4,
+        product_id -> Int4,
+        kind -> Varchar,
+        quantity -> Float8,
+        reason -> Varchar,
+        user_email -> Varchar,
+        user_company -> Varchar,
+        created_at -> Timestamp,
+        variant_id -> Nullable<Int4>,
+        location_id -> Nullable<Int4>,
     }
 }
 
@@ -14,10 +150,42 @@ table! {
         company -> Varchar,
         password -> Varchar,
         created_at -> Timestamp,
+        email_verified_at -> Nullable<Timestamp>,
+        sessions_invalidated_at -> Nullable<Timestamp>,
+        role -> Varchar,
+        totp_secret -> Nullable<Varchar>,
+        totp_enabled_at -> Nullable<Timestamp>,
+        totp_last_step -> Nullable<Int8>,
+        disabled_at -> Nullable<Timestamp>,
     }
 }
 
+joinable!(password_reset_tokens -> users (user_id));
+joinable!(product_categories -> categories (category_id));
+joinable!(product_categories -> products (product_id));
+joinable!(product_variants -> products (product_id));
+joinable!(recovery_codes -> users (user_id));
+joinable!(refresh_tokens -> users (user_id));
+joinable!(stock_levels -> locations (location_id));
+joinable!(stock_levels -> product_variants (variant_id));
+joinable!(stock_levels -> products (product_id));
+joinable!(stock_movements -> locations (location_id));
+joinable!(stock_movements -> product_variants (variant_id));
+joinable!(stock_movements -> products (product_id));
+
 allow_tables_to_appear_in_same_query!(
+    api_keys,
+    categories,
+    locations,
+    login_attempts,
+    password_reset_tokens,
+    product_categories,
+    product_variants,
     products,
+    recovery_codes,
+    refresh_tokens,
+    revoked_tokens,
+    stock_levels,
+    stock_movements,
     users,
 );
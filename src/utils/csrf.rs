@@ -0,0 +1,189 @@
+use std::rc::Rc;
+use std::sync::Arc;
+
+use actix_utils::future::{ready, Ready};
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderName, HeaderValue};
+use actix_web::http::Method;
+use actix_web::{web, Error, HttpMessage, HttpResponse};
+use csrf::{AesGcmCsrfProtection, CsrfProtection};
+use data_encoding::BASE64;
+use futures_util::future::LocalBoxFuture;
+
+use crate::config::{decode_key, CsrfSettings};
+use crate::db_connection::PgPool;
+use crate::errors::application_error::ApplicationError;
+use crate::errors::server_error::ServerError;
+use crate::handlers::run_blocking;
+use crate::models::api_key::ApiKey;
+use crate::utils::jwt::SlimUser;
+
+pub const CSRF_TOKEN_HEADER: HeaderName = HeaderName::from_static("x-csrf-token");
+pub const CSRF_COOKIE_HEADER: HeaderName = HeaderName::from_static("x-csrf-token-cookie");
+
+// Middleware that checks the csrf token pair on unsafe requests and hands out
+// a fresh pair, valid for `ttl_seconds`, on safe ones
+#[derive(Clone)]
+pub struct Csrf {
+    protection: Arc<AesGcmCsrfProtection>,
+    ttl_seconds: i64,
+}
+
+impl Csrf {
+    pub fn new(key: [u8; 32], ttl_seconds: i64) -> Csrf {
+        Csrf {
+            protection: Arc::new(AesGcmCsrfProtection::from_key(key)),
+            ttl_seconds,
+        }
+    }
+
+    pub fn from_settings(settings: &CsrfSettings) -> Result<Csrf, String> {
+        let key = decode_key(&settings.key, 32)
+            .ok()
+            .and_then(|key| <[u8; 32]>::try_from(key).ok())
+            .ok_or_else(|| "csrf.key must be 32 bytes encoded as base64".to_string())?;
+        Ok(Csrf::new(key, settings.token_ttl_seconds))
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Csrf
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = CsrfMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(CsrfMiddleware {
+            service: Rc::new(service),
+            protection: self.protection.clone(),
+            ttl_seconds: self.ttl_seconds,
+        }))
+    }
+}
+
+pub struct CsrfMiddleware<S> {
+    service: Rc<S>,
+    protection: Arc<AesGcmCsrfProtection>,
+    ttl_seconds: i64,
+}
+
+fn is_safe(method: &Method) -> bool {
+    matches!(*method, Method::GET | Method::HEAD | Method::OPTIONS)
+}
+
+// The user of an API key this middleware already validated, taken by the
+// `LoggedUser` extractor instead of looking the key up a second time
+pub struct ApiKeyUser(pub SlimUser);
+
+fn bearer_key(req: &ServiceRequest) -> Option<String> {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|key| key.trim().to_string())
+}
+
+// Requests authenticated with a valid API key skip the token check, browsers
+// never attach the key to a cross-site request on their own. Invalid keys get
+// no exemption, so any bearer header cannot switch the check off.
+async fn api_key_user(req: &ServiceRequest) -> Result<Option<SlimUser>, ServerError> {
+    let (Some(key), Some(pool)) = (bearer_key(req), req.app_data::<web::Data<PgPool>>()) else {
+        return Ok(None);
+    };
+    run_blocking(pool.clone(), move |conn| {
+        match ApiKey::authenticate(&key, conn) {
+            Ok(api_key) => Ok(Some(api_key.into())),
+            Err(ApplicationError::InvalidToken(_)) => Ok(None),
+            Err(e) => Err(e),
+        }
+    })
+    .await
+}
+
+fn decode_header(req: &ServiceRequest, name: &HeaderName) -> Result<Vec<u8>, ServerError> {
+    let value = req
+        .headers()
+        .get(name)
+        .ok_or_else(|| ServerError::Forbidden(format!("Missing {} header", name)))?;
+    BASE64
+        .decode(value.as_bytes())
+        .map_err(|_| ServerError::Forbidden(format!("Invalid {} header", name)))
+}
+
+fn verify(protection: &AesGcmCsrfProtection, req: &ServiceRequest) -> Result<(), ServerError> {
+    let token = decode_header(req, &CSRF_TOKEN_HEADER)?;
+    let cookie = decode_header(req, &CSRF_COOKIE_HEADER)?;
+    let token = protection
+        .parse_token(&token)
+        .map_err(|_| ServerError::Forbidden("Invalid CSRF token".to_string()))?;
+    let cookie = protection
+        .parse_cookie(&cookie)
+        .map_err(|_| ServerError::Forbidden("Invalid CSRF token cookie".to_string()))?;
+    if !protection.verify_token_pair(&token, &cookie) {
+        return Err(ServerError::Forbidden(
+            "Invalid or expired CSRF token pair".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+impl<S, B> Service<ServiceRequest> for CsrfMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        if is_safe(req.method()) {
+            let pair = self.protection.generate_token_pair(None, self.ttl_seconds);
+            let service = self.service.clone();
+            return Box::pin(async move {
+                let mut res = service.call(req).await?;
+                match pair {
+                    Ok((token, cookie)) => {
+                        let headers = res.headers_mut();
+                        for (name, value) in [
+                            (CSRF_TOKEN_HEADER, token.b64_string()),
+                            (CSRF_COOKIE_HEADER, cookie.b64_string()),
+                        ] {
+                            if let Ok(value) = HeaderValue::from_str(&value) {
+                                headers.insert(name, value);
+                            }
+                        }
+                    }
+                    Err(err) => log::error!("Failed to generate CSRF token: {}", err),
+                }
+                Ok(res.map_into_left_body())
+            });
+        }
+
+        let service = self.service.clone();
+        let protection = self.protection.clone();
+        Box::pin(async move {
+            let checked = match api_key_user(&req).await {
+                Ok(Some(user)) => {
+                    req.extensions_mut().insert(ApiKeyUser(user));
+                    Ok(())
+                }
+                Ok(None) => verify(&protection, &req),
+                Err(err) => Err(err),
+            };
+            if let Err(err) = checked {
+                let response = HttpResponse::from_error(err).map_into_right_body();
+                return Ok(req.into_response(response));
+            }
+            Ok(service.call(req).await?.map_into_left_body())
+        })
+    }
+}
@@ -1,21 +1,40 @@
-use std::env;
-
 use chrono::{Duration, Local};
-use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
+use jsonwebtoken::errors::ErrorKind;
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, Header, Validation};
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 
 use crate::errors::server_error::ServerError;
+use crate::models::api_key::ApiScope;
+use crate::models::role::Role;
+use crate::utils::keys::key_store;
+use crate::utils::token::generate_token;
+
+// Access tokens are short-lived, clients renew them with a refresh token
+pub const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
 
 #[derive(Debug, Deserialize, Serialize)]
 pub struct Claims {
     pub sub: String, // this is the email
     pub exp: usize,
+    pub iat: usize,
+    pub jti: String,
     pub company: String,
+    pub role: Role,
 }
 
+#[derive(Clone)]
 pub struct SlimUser {
     pub email: String,
     pub company: String,
+    pub issued_at: usize,
+    pub expires_at: usize,
+    pub token_id: String,
+    pub role: Role,
+    // set when the request was authenticated with an API key
+    pub api_key_id: Option<i32>,
+    // what the API key may do, empty for user sessions
+    pub api_scopes: Vec<ApiScope>,
 }
 
 impl From<Claims> for SlimUser {
@@ -23,49 +42,92 @@ impl From<Claims> for SlimUser {
         SlimUser {
             email: claims.sub,
             company: claims.company,
+            issued_at: claims.iat,
+            expires_at: claims.exp,
+            token_id: claims.jti,
+            role: claims.role,
+            api_key_id: None,
+            api_scopes: Vec::new(),
         }
     }
 }
 
 impl Claims {
-    pub fn new(email: &str, company: &str) -> Claims {
+    pub fn new(email: &str, company: &str, role: Role) -> Claims {
+        let now = Local::now();
         Claims {
             sub: email.to_string(),
             company: company.to_string(),
-            exp: (Local::now() + Duration::hours(24)).timestamp() as usize,
+            exp: (now + Duration::minutes(ACCESS_TOKEN_TTL_MINUTES)).timestamp() as usize,
+            iat: now.timestamp() as usize,
+            jti: generate_token(),
+            role,
         }
     }
 }
 
-pub fn create_token(email: &str, company: &str) -> Result<String, ServerError> {
-    let claims = Claims::new(email, company);
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(&get_secret()),
-    )
-    .map_err(|err| {
-        println!("{}", err);
-        ServerError::InternalServerError(err.to_string())
-    })
+pub fn create_token(email: &str, company: &str, role: Role) -> Result<String, ServerError> {
+    sign(&Claims::new(email, company, role))
 }
 
 pub fn decode_token(token: &str) -> Result<SlimUser, ServerError> {
-    decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(&get_secret()),
-        &Validation::default(),
-    )
-    .map(|data| data.claims.into())
-    .map_err(|err| {
-        println!("{}", err);
-        ServerError::InternalServerError(err.to_string())
+    verify::<Claims>(token)
+        .map(|claims| claims.into())
+        .map_err(|_| ServerError::Unauthorized("Invalid or expired session".to_string()))
+}
+
+// Claims of a single-purpose token such as an email verification link
+#[derive(Debug, Deserialize, Serialize)]
+pub struct PurposeClaims {
+    pub sub: String, // this is the email
+    pub exp: usize,
+    pub purpose: String,
+}
+
+pub const VERIFY_EMAIL_PURPOSE: &str = "verify_email";
+// issued after the password of a two-factor login was checked
+pub const TWO_FACTOR_PURPOSE: &str = "two_factor";
+
+pub fn create_purpose_token(
+    email: &str,
+    purpose: &str,
+    valid_for: Duration,
+) -> Result<String, ServerError> {
+    sign(&PurposeClaims {
+        sub: email.to_string(),
+        exp: (Local::now() + valid_for).timestamp() as usize,
+        purpose: purpose.to_string(),
     })
 }
 
-// get secret as bytes from .env file using dot env
-fn get_secret() -> Vec<u8> {
-    env::var("SECRET")
-        .expect("SECRET must be set in .env file")
-        .into_bytes()
+// Decode a single-purpose token and return the email it was issued for
+pub fn decode_purpose_token(token: &str, purpose: &str) -> Result<String, ServerError> {
+    let claims = verify::<PurposeClaims>(token)
+        .map_err(|_| ServerError::BadRequest("Invalid or expired token".to_string()))?;
+    if claims.purpose != purpose {
+        return Err(ServerError::BadRequest(
+            "Invalid or expired token".to_string(),
+        ));
+    }
+    Ok(claims.sub)
+}
+
+// Sign claims with the active key, its id goes in the `kid` header
+fn sign<T: Serialize>(claims: &T) -> Result<String, ServerError> {
+    let keys = key_store();
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some(keys.signing_kid().to_string());
+    encode(&header, claims, keys.signing_key())
+        .map_err(|err| ServerError::InternalServerError(err.to_string()))
+}
+
+// Verify a token with the key named by its `kid` header
+fn verify<T: DeserializeOwned>(token: &str) -> Result<T, jsonwebtoken::errors::Error> {
+    let header = decode_header(token)?;
+    let key = header
+        .kid
+        .as_deref()
+        .and_then(|kid| key_store().decoding_key(kid))
+        .ok_or(ErrorKind::InvalidSignature)?;
+    decode::<T>(token, key, &Validation::new(Algorithm::EdDSA)).map(|data| data.claims)
 }
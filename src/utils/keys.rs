@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use data_encoding::BASE64URL_NOPAD;
+use jsonwebtoken::{DecodingKey, EncodingKey};
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde::Serialize;
+
+use crate::config::JwtSettings;
+
+// DER prefix of an Ed25519 SubjectPublicKeyInfo, followed by the 32 key bytes
+const ED25519_SPKI_PREFIX: [u8; 12] = [
+    0x30, 0x2a, 0x30, 0x05, 0x06, 0x03, 0x2b, 0x65, 0x70, 0x03, 0x21, 0x00,
+];
+
+static KEY_STORE: OnceLock<KeyStore> = OnceLock::new();
+
+// Ed25519 keys used to sign and verify tokens. Tokens are signed with the
+// active key and carry its `kid`; any key of `verification` can verify them,
+// so a new key can be published before it is used to sign and an old one kept
+// until the tokens it signed have expired.
+pub struct KeyStore {
+    signing_kid: String,
+    signing_key: EncodingKey,
+    verification: HashMap<String, VerificationKey>,
+}
+
+struct VerificationKey {
+    decoding_key: DecodingKey,
+    public_key: Vec<u8>,
+}
+
+// A public key in JSON Web Key format
+#[derive(Serialize)]
+pub struct Jwk {
+    pub kty: &'static str,
+    pub crv: &'static str,
+    pub alg: &'static str,
+    #[serde(rename = "use")]
+    pub key_use: &'static str,
+    pub kid: String,
+    pub x: String,
+}
+
+#[derive(Serialize)]
+pub struct Jwks {
+    pub keys: Vec<Jwk>,
+}
+
+impl KeyStore {
+    // Build a store signing with the PKCS#8 encoded Ed25519 private key
+    pub fn new(signing_kid: &str, private_key_pem: &[u8]) -> Result<KeyStore, String> {
+        let der = pem::parse(private_key_pem)
+            .map_err(|err| format!("Invalid signing key {}: {}", signing_kid, err))?
+            .contents;
+        let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(&der)
+            .map_err(|err| format!("Invalid Ed25519 signing key {}: {}", signing_kid, err))?;
+        let mut store = KeyStore {
+            signing_kid: signing_kid.to_string(),
+            signing_key: EncodingKey::from_ed_der(&der),
+            verification: HashMap::new(),
+        };
+        store.add_public_key(signing_kid, key_pair.public_key().as_ref().to_vec());
+        Ok(store)
+    }
+
+    // Accept tokens signed with the Ed25519 public key in SPKI PEM format
+    pub fn add_public_key_pem(&mut self, kid: &str, public_key_pem: &[u8]) -> Result<(), String> {
+        let der = pem::parse(public_key_pem)
+            .map_err(|err| format!("Invalid verification key {}: {}", kid, err))?
+            .contents;
+        if der.len() != ED25519_SPKI_PREFIX.len() + 32 || !der.starts_with(&ED25519_SPKI_PREFIX) {
+            return Err(format!(
+                "Verification key {} is not an Ed25519 public key",
+                kid
+            ));
+        }
+        self.add_public_key(kid, der[ED25519_SPKI_PREFIX.len()..].to_vec());
+        Ok(())
+    }
+
+    fn add_public_key(&mut self, kid: &str, public_key: Vec<u8>) {
+        self.verification.insert(
+            kid.to_string(),
+            VerificationKey {
+                decoding_key: DecodingKey::from_ed_der(&public_key),
+                public_key,
+            },
+        );
+    }
+
+    // Load the active private key and the optional directory of
+    // `<kid>.pub.pem` files from the jwt settings
+    pub fn from_settings(settings: &JwtSettings) -> Result<KeyStore, String> {
+        let path = &settings.signing_key_file;
+        let private_key =
+            fs::read(path).map_err(|err| format!("Cannot read signing key {}: {}", path, err))?;
+        let mut store = KeyStore::new(&settings.signing_kid, &private_key)?;
+        if let Some(dir) = &settings.verification_keys_dir {
+            store.load_public_keys(Path::new(dir))?;
+        }
+        Ok(store)
+    }
+
+    fn load_public_keys(&mut self, dir: &Path) -> Result<(), String> {
+        let entries = fs::read_dir(dir)
+            .map_err(|err| format!("Cannot read key directory {}: {}", dir.display(), err))?;
+        for entry in entries {
+            let path = entry.map_err(|err| err.to_string())?.path();
+            let file_name = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .unwrap_or("");
+            if let Some(kid) = file_name.strip_suffix(".pub.pem") {
+                let pem = fs::read(&path)
+                    .map_err(|err| format!("Cannot read {}: {}", path.display(), err))?;
+                self.add_public_key_pem(kid, &pem)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn signing_kid(&self) -> &str {
+        &self.signing_kid
+    }
+
+    pub fn signing_key(&self) -> &EncodingKey {
+        &self.signing_key
+    }
+
+    pub fn decoding_key(&self, kid: &str) -> Option<&DecodingKey> {
+        self.verification.get(kid).map(|key| &key.decoding_key)
+    }
+
+    // Public keys published on the JWKS endpoint, sorted by kid
+    pub fn jwks(&self) -> Jwks {
+        let mut keys: Vec<Jwk> = self
+            .verification
+            .iter()
+            .map(|(kid, key)| Jwk {
+                kty: "OKP",
+                crv: "Ed25519",
+                alg: "EdDSA",
+                key_use: "sig",
+                kid: kid.clone(),
+                x: BASE64URL_NOPAD.encode(&key.public_key),
+            })
+            .collect();
+        keys.sort_by(|a, b| a.kid.cmp(&b.kid));
+        Jwks { keys }
+    }
+}
+
+// Install the key store, called once at startup
+pub fn init(store: KeyStore) {
+    if KEY_STORE.set(store).is_err() {
+        panic!("The JWT key store is already initialized");
+    }
+}
+
+pub fn key_store() -> &'static KeyStore {
+    KEY_STORE
+        .get()
+        .expect("The JWT key store must be initialized at startup")
+}
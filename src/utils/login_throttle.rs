@@ -0,0 +1,212 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use redis::aio::ConnectionManager;
+use redis::AsyncCommands;
+
+// Failed attempts are counted over a sliding window
+pub const FAILURE_WINDOW: Duration = Duration::from_secs(15 * 60);
+// Failures allowed before logins get delayed
+pub const FREE_FAILURES: u32 = 3;
+pub const MAX_DELAY: Duration = Duration::from_secs(8);
+// Failures after which the account or the ip is locked for the window
+pub const ACCOUNT_LOCKOUT_THRESHOLD: u32 = 10;
+pub const IP_LOCKOUT_THRESHOLD: u32 = 50;
+
+// Actions counted separately, so resending verification emails cannot lock
+// anyone out of logging in
+pub const LOGIN: &str = "login";
+pub const VERIFY_RESEND: &str = "verify_resend";
+
+// Counts failed logins, or other throttled requests, per account and per ip. Counters live in Redis so
+// every replica sees them; when Redis is unavailable they are kept in memory.
+pub struct LoginThrottle {
+    redis: Option<ConnectionManager>,
+    memory: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+// Failures currently counted for a login
+pub struct LoginFailures {
+    pub account: u32,
+    pub ip: u32,
+}
+
+impl LoginFailures {
+    pub fn is_locked(&self) -> bool {
+        self.account >= ACCOUNT_LOCKOUT_THRESHOLD || self.ip >= IP_LOCKOUT_THRESHOLD
+    }
+
+    // Delay applied before checking the password, doubling with every
+    // failure past the free ones
+    pub fn delay(&self) -> Duration {
+        let failures = self.account.max(self.ip);
+        if failures <= FREE_FAILURES {
+            return Duration::ZERO;
+        }
+        let exponent = (failures - FREE_FAILURES - 1).min(16);
+        Duration::from_millis(500 * 2u64.pow(exponent)).min(MAX_DELAY)
+    }
+}
+
+impl LoginThrottle {
+    pub fn in_memory() -> LoginThrottle {
+        LoginThrottle {
+            redis: None,
+            memory: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Use Redis at `redis_url`, falling back to memory if it cannot be reached
+    pub async fn connect(redis_url: &str) -> LoginThrottle {
+        let client = match redis::Client::open(redis_url) {
+            Ok(client) => client,
+            Err(err) => {
+                log::warn!("Invalid Redis url for login throttling: {}", err);
+                return LoginThrottle::in_memory();
+            }
+        };
+        match ConnectionManager::new(client).await {
+            Ok(manager) => LoginThrottle {
+                redis: Some(manager),
+                ..LoginThrottle::in_memory()
+            },
+            Err(err) => {
+                log::warn!(
+                    "Redis unavailable, login throttling falls back to memory: {}",
+                    err
+                );
+                LoginThrottle::in_memory()
+            }
+        }
+    }
+
+    fn account_key(action: &str, email: &str) -> String {
+        format!("{}:failures:account:{}", action, email.to_lowercase())
+    }
+
+    fn ip_key(action: &str, ip: &str) -> String {
+        format!("{}:failures:ip:{}", action, ip)
+    }
+
+    pub async fn failures(&self, email: &str, ip: &str) -> LoginFailures {
+        self.counted(LOGIN, email, ip).await
+    }
+
+    pub async fn record_failure(&self, email: &str, ip: &str) {
+        self.count(LOGIN, email, ip).await;
+    }
+
+    // A successful login clears the account counter, the ip one keeps
+    // counting so one valid account cannot be used to reset it
+    pub async fn record_success(&self, email: &str) {
+        self.reset(&Self::account_key(LOGIN, email)).await;
+    }
+
+    // Requests of `action` counted for the account and the ip, with the same
+    // window and lockout thresholds as failed logins
+    pub async fn counted(&self, action: &str, email: &str, ip: &str) -> LoginFailures {
+        LoginFailures {
+            account: self.get(&Self::account_key(action, email)).await,
+            ip: self.get(&Self::ip_key(action, ip)).await,
+        }
+    }
+
+    pub async fn count(&self, action: &str, email: &str, ip: &str) {
+        self.increment(&Self::account_key(action, email)).await;
+        self.increment(&Self::ip_key(action, ip)).await;
+    }
+
+    async fn get(&self, key: &str) -> u32 {
+        if let Some(mut redis) = self.redis.clone() {
+            match redis.get::<_, Option<u32>>(key).await {
+                Ok(count) => return count.unwrap_or(0),
+                Err(err) => log::warn!("Redis error reading {}: {}", key, err),
+            }
+        }
+        let memory = self.memory.lock().unwrap();
+        match memory.get(key) {
+            Some((count, expires_at)) if *expires_at > Instant::now() => *count,
+            _ => 0,
+        }
+    }
+
+    async fn increment(&self, key: &str) {
+        if let Some(mut redis) = self.redis.clone() {
+            let result = redis::pipe()
+                .atomic()
+                .incr(key, 1)
+                .ignore()
+                .expire(key, FAILURE_WINDOW.as_secs() as usize)
+                .ignore()
+                .query_async::<_, ()>(&mut redis)
+                .await;
+            match result {
+                Ok(()) => return,
+                Err(err) => log::warn!("Redis error incrementing {}: {}", key, err),
+            }
+        }
+        let now = Instant::now();
+        let mut memory = self.memory.lock().unwrap();
+        // forget expired counters so the map does not grow forever
+        memory.retain(|_, (_, expires_at)| *expires_at > now);
+        let entry = memory.entry(key.to_string()).or_insert((0, now));
+        entry.0 += 1;
+        entry.1 = now + FAILURE_WINDOW;
+    }
+
+    async fn reset(&self, key: &str) {
+        if let Some(mut redis) = self.redis.clone() {
+            if let Err(err) = redis.del::<_, ()>(key).await {
+                log::warn!("Redis error resetting {}: {}", key, err);
+            }
+        }
+        self.memory.lock().unwrap().remove(key);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn failures(account: u32, ip: u32) -> LoginFailures {
+        LoginFailures { account, ip }
+    }
+
+    #[test]
+    fn lets_the_first_failures_through_without_delay() {
+        for count in 0..=FREE_FAILURES {
+            assert_eq!(failures(count, 0).delay(), Duration::ZERO);
+        }
+    }
+
+    #[test]
+    fn doubles_the_delay_up_to_the_maximum() {
+        assert_eq!(failures(4, 0).delay(), Duration::from_millis(500));
+        assert_eq!(failures(5, 0).delay(), Duration::from_secs(1));
+        assert_eq!(failures(0, 7).delay(), Duration::from_secs(4));
+        assert_eq!(failures(8, 0).delay(), MAX_DELAY);
+        assert_eq!(failures(u32::MAX, 0).delay(), MAX_DELAY);
+    }
+
+    #[test]
+    fn locks_either_the_account_or_the_ip() {
+        assert!(!failures(ACCOUNT_LOCKOUT_THRESHOLD - 1, IP_LOCKOUT_THRESHOLD - 1).is_locked());
+        assert!(failures(ACCOUNT_LOCKOUT_THRESHOLD, 0).is_locked());
+        assert!(failures(0, IP_LOCKOUT_THRESHOLD).is_locked());
+    }
+
+    #[actix_web::test]
+    async fn counts_in_memory_until_a_success() {
+        let throttle = LoginThrottle::in_memory();
+        throttle.record_failure("A@x.io", "203.0.113.7").await;
+        throttle.record_failure("a@x.io", "203.0.113.7").await;
+        let counted = throttle.failures("a@x.io", "203.0.113.7").await;
+        assert_eq!((counted.account, counted.ip), (2, 2));
+
+        // a success clears the account but not the ip
+        throttle.record_success("a@x.io").await;
+        let counted = throttle.failures("a@x.io", "203.0.113.7").await;
+        assert_eq!((counted.account, counted.ip), (0, 2));
+    }
+}
@@ -0,0 +1,56 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+use actix_web::web;
+use chrono::Local;
+use serde::Serialize;
+
+// An outgoing email
+#[derive(Debug, Serialize)]
+pub struct Email {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+// Anything able to deliver an email. Handlers take a `web::Data<dyn Mailer>`
+// so the transport can be swapped without touching them.
+pub trait Mailer: Send + Sync {
+    fn send(&self, email: &Email) -> io::Result<()>;
+}
+
+// Deliver an email on the blocking thread pool, sending is file or network
+// I/O that must not stall the async workers
+pub async fn send_blocking(mailer: web::Data<dyn Mailer>, email: Email) -> io::Result<()> {
+    web::block(move || mailer.send(&email))
+        .await
+        .map_err(io::Error::other)?
+}
+
+// Mailer writing every email as a JSON file into an outbox directory,
+// used for local development and tests.
+pub struct FileMailer {
+    outbox: PathBuf,
+}
+
+impl FileMailer {
+    pub fn new<P: Into<PathBuf>>(outbox: P) -> FileMailer {
+        FileMailer {
+            outbox: outbox.into(),
+        }
+    }
+}
+
+impl Mailer for FileMailer {
+    fn send(&self, email: &Email) -> io::Result<()> {
+        fs::create_dir_all(&self.outbox)?;
+        let file_name = format!(
+            "{}-{}.json",
+            Local::now().format("%Y%m%dT%H%M%S%.f"),
+            email.to.replace(|c: char| !c.is_ascii_alphanumeric(), "_")
+        );
+        let content = serde_json::to_vec_pretty(email)?;
+        fs::write(self.outbox.join(file_name), content)
+    }
+}
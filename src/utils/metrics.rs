@@ -0,0 +1,234 @@
+use std::rc::Rc;
+use std::sync::{LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+use actix_utils::future::{ready, Ready};
+use actix_web::dev::{forward_ready, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::Error;
+use diesel::r2d2::{event, HandleEvent};
+use futures_util::future::LocalBoxFuture;
+use prometheus::{
+    exponential_buckets, Encoder, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter,
+    IntCounterVec, IntGauge, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+
+// Stock gauges sum the products table, frequent scrapes reuse the last sample
+const STOCK_SAMPLE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Collectors exposed on /metrics
+pub struct Metrics {
+    registry: Registry,
+    pub http_requests: IntCounterVec,
+    pub http_request_duration: HistogramVec,
+    pub db_pool_connections: IntGauge,
+    pub db_pool_idle_connections: IntGauge,
+    pub db_pool_wait: Histogram,
+    pub db_pool_timeouts: IntCounter,
+    pub login_attempts: IntCounterVec,
+    pub company_stock: GaugeVec,
+    pub company_products_out_of_stock: IntGaugeVec,
+    stock_sampled_at: Mutex<Option<Instant>>,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+impl Metrics {
+    fn new() -> Metrics {
+        let registry = Registry::new();
+        let http_requests = IntCounterVec::new(
+            Opts::new("http_requests_total", "HTTP requests by route and status"),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let http_request_duration = HistogramVec::new(
+            HistogramOpts::new(
+                "http_request_duration_seconds",
+                "HTTP request latency by route and status",
+            ),
+            &["method", "route", "status"],
+        )
+        .unwrap();
+        let db_pool_connections = IntGauge::new(
+            "db_pool_connections",
+            "Connections currently held by the database pool",
+        )
+        .unwrap();
+        let db_pool_idle_connections = IntGauge::new(
+            "db_pool_idle_connections",
+            "Idle connections in the database pool",
+        )
+        .unwrap();
+        let db_pool_wait = Histogram::with_opts(
+            HistogramOpts::new(
+                "db_pool_wait_seconds",
+                "Time spent waiting for a database connection",
+            )
+            .buckets(exponential_buckets(0.0005, 4.0, 9).unwrap()),
+        )
+        .unwrap();
+        let db_pool_timeouts = IntCounter::new(
+            "db_pool_timeouts_total",
+            "Database connection requests that timed out",
+        )
+        .unwrap();
+        let login_attempts = IntCounterVec::new(
+            Opts::new("login_attempts_total", "Login attempts by outcome"),
+            &["outcome"],
+        )
+        .unwrap();
+        // one series per company, never per product
+        let company_stock = GaugeVec::new(
+            Opts::new(
+                "company_stock",
+                "Stock on hand summed over the products of a company",
+            ),
+            &["company"],
+        )
+        .unwrap();
+        let company_products_out_of_stock = IntGaugeVec::new(
+            Opts::new(
+                "company_products_out_of_stock",
+                "Products of a company without stock on hand",
+            ),
+            &["company"],
+        )
+        .unwrap();
+
+        registry.register(Box::new(http_requests.clone())).unwrap();
+        registry
+            .register(Box::new(http_request_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_connections.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(db_pool_idle_connections.clone()))
+            .unwrap();
+        registry.register(Box::new(db_pool_wait.clone())).unwrap();
+        registry
+            .register(Box::new(db_pool_timeouts.clone()))
+            .unwrap();
+        registry.register(Box::new(login_attempts.clone())).unwrap();
+        registry.register(Box::new(company_stock.clone())).unwrap();
+        registry
+            .register(Box::new(company_products_out_of_stock.clone()))
+            .unwrap();
+
+        Metrics {
+            registry,
+            http_requests,
+            http_request_duration,
+            db_pool_connections,
+            db_pool_idle_connections,
+            db_pool_wait,
+            db_pool_timeouts,
+            login_attempts,
+            company_stock,
+            company_products_out_of_stock,
+            stock_sampled_at: Mutex::new(None),
+        }
+    }
+
+    // Whether the stock gauges are stale, claims the next sample when they are
+    pub fn stock_sample_due(&self) -> bool {
+        let mut sampled_at = self.stock_sampled_at.lock().unwrap();
+        if sampled_at.is_some_and(|at| at.elapsed() < STOCK_SAMPLE_INTERVAL) {
+            return false;
+        }
+        *sampled_at = Some(Instant::now());
+        true
+    }
+
+    // Everything in the prometheus text format
+    pub fn encode(&self) -> Result<String, String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .map_err(|err| err.to_string())?;
+        String::from_utf8(buffer).map_err(|err| err.to_string())
+    }
+}
+
+// Pool event handler recording how long checkouts wait
+#[derive(Debug)]
+pub struct PoolMetrics;
+
+impl HandleEvent for PoolMetrics {
+    fn handle_checkout(&self, event: event::CheckoutEvent) {
+        metrics()
+            .db_pool_wait
+            .observe(event.duration().as_secs_f64());
+    }
+
+    fn handle_timeout(&self, event: event::TimeoutEvent) {
+        metrics().db_pool_timeouts.inc();
+        metrics()
+            .db_pool_wait
+            .observe(event.timeout().as_secs_f64());
+    }
+}
+
+// Middleware counting requests and their latency per matched route, so
+// `/products/1` and `/products/2` share the `/products/{id}` series
+pub struct RequestMetrics;
+
+impl<S, B> Transform<S, ServiceRequest> for RequestMetrics
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = RequestMetricsMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(RequestMetricsMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct RequestMetricsMiddleware<S> {
+    service: Rc<S>,
+}
+
+impl<S, B> Service<ServiceRequest> for RequestMetricsMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let started = Instant::now();
+        let method = req.method().to_string();
+        let service = self.service.clone();
+        Box::pin(async move {
+            let res = service.call(req).await?;
+            // unknown paths share one series instead of one per url
+            let route = res
+                .request()
+                .match_pattern()
+                .unwrap_or_else(|| "unmatched".to_string());
+            let status = res.status().as_u16().to_string();
+            let labels = [method.as_str(), route.as_str(), status.as_str()];
+            let metrics = metrics();
+            metrics.http_requests.with_label_values(&labels).inc();
+            metrics
+                .http_request_duration
+                .with_label_values(&labels)
+                .observe(started.elapsed().as_secs_f64());
+            Ok(res)
+        })
+    }
+}
@@ -1 +1,9 @@
+pub mod csrf;
 pub mod jwt;
+pub mod keys;
+pub mod login_throttle;
+pub mod mailer;
+pub mod metrics;
+pub mod token;
+pub mod totp;
+pub mod validation;
@@ -0,0 +1,16 @@
+use data_encoding::{BASE64URL_NOPAD, HEXLOWER};
+use rand::RngCore;
+use sha2::{Digest, Sha256};
+
+// Generate a random url-safe token with 256 bits of entropy
+pub fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE64URL_NOPAD.encode(&bytes)
+}
+
+// Hash a token with SHA-256 so it can be stored and looked up without keeping
+// the token itself in the database
+pub fn hash_token(token: &str) -> String {
+    HEXLOWER.encode(&Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,126 @@
+use data_encoding::BASE32_NOPAD;
+use percent_encoding::{utf8_percent_encode, NON_ALPHANUMERIC};
+use rand::RngCore;
+use ring::hmac;
+
+// RFC 6238 parameters understood by every authenticator app
+pub const TOTP_STEP_SECONDS: i64 = 30;
+pub const TOTP_DIGITS: u32 = 6;
+// accepted clock drift in steps on either side of the current one
+const TOTP_SKEW: i64 = 1;
+
+pub const TOTP_ISSUER: &str = "rust_store";
+
+// Generate a random 160 bit secret, base32 encoded as authenticator apps expect
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; 20];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    BASE32_NOPAD.encode(&bytes)
+}
+
+// otpauth:// uri that authenticator apps read from a QR code
+pub fn provisioning_uri(account: &str, secret: &str) -> String {
+    let label = format!("{}:{}", TOTP_ISSUER, account);
+    format!(
+        "otpauth://totp/{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        utf8_percent_encode(&label, NON_ALPHANUMERIC),
+        secret,
+        utf8_percent_encode(TOTP_ISSUER, NON_ALPHANUMERIC),
+        TOTP_DIGITS,
+        TOTP_STEP_SECONDS
+    )
+}
+
+// Time step of a unix timestamp
+pub fn time_step(unix_seconds: i64) -> i64 {
+    unix_seconds.div_euclid(TOTP_STEP_SECONDS)
+}
+
+// HOTP value (RFC 4226) of the secret for a time step
+fn code_at(key: &[u8], step: i64) -> u32 {
+    let key = hmac::Key::new(hmac::HMAC_SHA1_FOR_LEGACY_USE_ONLY, key);
+    let digest = hmac::sign(&key, &step.to_be_bytes());
+    let digest = digest.as_ref();
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+    binary % 10u32.pow(TOTP_DIGITS)
+}
+
+// Check a code against the secret around `now`. Returns the matched time step
+// so the caller can refuse steps at or before the last accepted one.
+pub fn verify_code(secret: &str, code: &str, now: i64, last_step: Option<i64>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != TOTP_DIGITS as usize || !code.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    let code: u32 = code.parse().ok()?;
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let current = time_step(now);
+    (current - TOTP_SKEW..=current + TOTP_SKEW)
+        .filter(|step| last_step.is_none_or(|last| *step > last))
+        .find(|step| {
+            ring::constant_time::verify_slices_are_equal(
+                &code_at(&key, *step).to_be_bytes(),
+                &code.to_be_bytes(),
+            )
+            .is_ok()
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // the SHA-1 secret of the RFC 6238 test vectors, "12345678901234567890"
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    #[test]
+    fn matches_the_rfc_test_vectors() {
+        assert_eq!(verify_code(SECRET, "287082", 59, None), Some(1));
+        assert_eq!(
+            verify_code(SECRET, "081804", 1_111_111_109, None),
+            Some(time_step(1_111_111_109))
+        );
+        assert_eq!(
+            verify_code(SECRET, "005924", 1_234_567_890, None),
+            Some(time_step(1_234_567_890))
+        );
+    }
+
+    #[test]
+    fn accepts_one_step_of_drift_on_either_side() {
+        // the code of step 1 is still accepted during steps 0 and 2
+        assert_eq!(verify_code(SECRET, "287082", 59 + 30, None), Some(1));
+        assert_eq!(verify_code(SECRET, "287082", 0, None), Some(1));
+        assert_eq!(verify_code(SECRET, "287082", 59 + 60, None), None);
+    }
+
+    #[test]
+    fn refuses_steps_already_used() {
+        assert_eq!(verify_code(SECRET, "287082", 59, Some(1)), None);
+        assert_eq!(verify_code(SECRET, "287082", 59, Some(0)), Some(1));
+    }
+
+    #[test]
+    fn rejects_malformed_codes() {
+        for code in ["", "28708", "2870822", "28708a", "287 082"] {
+            assert_eq!(verify_code(SECRET, code, 59, None), None, "{:?}", code);
+        }
+        assert_eq!(verify_code(SECRET, " 287082 ", 59, None), Some(1));
+        assert_eq!(verify_code("not base32!", "287082", 59, None), None);
+    }
+
+    #[test]
+    fn generates_secrets_apps_can_read() {
+        let secret = generate_secret();
+        assert_eq!(BASE32_NOPAD.decode(secret.as_bytes()).unwrap().len(), 20);
+        let uri = provisioning_uri("a@b.io", &secret);
+        assert!(uri.starts_with("otpauth://totp/rust%5Fstore%3Aa%40b%2Eio?secret="));
+        assert!(uri.ends_with("&digits=6&period=30"));
+    }
+}
@@ -0,0 +1,94 @@
+use actix_web::{web, FromRequest, HttpRequest};
+use futures_util::future::LocalBoxFuture;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use validator::{Validate, ValidationError, ValidationErrors};
+
+use crate::errors::server_error::ServerError;
+
+// Bcrypt only uses the first 72 bytes of a password
+pub const PASSWORD_MAX_BYTES: usize = 72;
+pub const PASSWORD_MIN_CHARS: usize = 8;
+
+// A single invalid field of a request body
+#[derive(Debug, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub code: String,
+    pub message: String,
+}
+
+// Flatten validator errors into a list sorted by field name
+pub fn field_errors(errors: &ValidationErrors) -> Vec<FieldError> {
+    let mut fields: Vec<FieldError> = errors
+        .field_errors()
+        .into_iter()
+        .flat_map(|(field, errors)| {
+            errors.iter().map(move |error| FieldError {
+                field: field.to_string(),
+                code: error.code.to_string(),
+                message: error
+                    .message
+                    .as_ref()
+                    .map(|message| message.to_string())
+                    .unwrap_or_else(|| format!("{} is invalid", field)),
+            })
+        })
+        .collect();
+    fields.sort_by(|a, b| a.field.cmp(&b.field));
+    fields
+}
+
+// Password strength rule shared by registration and password reset
+pub fn validate_password_strength(password: &str) -> Result<(), ValidationError> {
+    let error = |message: &'static str| {
+        let mut error = ValidationError::new("password_strength");
+        error.message = Some(message.into());
+        error
+    };
+    if password.chars().count() < PASSWORD_MIN_CHARS {
+        return Err(error("Password must be at least 8 characters long"));
+    }
+    if password.len() > PASSWORD_MAX_BYTES {
+        return Err(error("Password must be at most 72 bytes long"));
+    }
+    if !password.chars().any(|c| c.is_alphabetic()) || !password.chars().any(|c| c.is_numeric()) {
+        return Err(error("Password must contain both letters and digits"));
+    }
+    Ok(())
+}
+
+// Json extractor that runs the body's `Validate` rules before the handler is
+// called, answering 422 with every field error otherwise.
+pub struct ValidatedJson<T>(pub T);
+
+impl<T> ValidatedJson<T> {
+    pub fn into_inner(self) -> T {
+        self.0
+    }
+}
+
+impl<T> std::ops::Deref for ValidatedJson<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> FromRequest for ValidatedJson<T>
+where
+    T: DeserializeOwned + Validate + 'static,
+{
+    type Error = actix_web::Error;
+    type Future = LocalBoxFuture<'static, Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, payload: &mut actix_web::dev::Payload) -> Self::Future {
+        let json = web::Json::<T>::from_request(req, payload);
+        Box::pin(async move {
+            let json = json.await?;
+            json.validate().map_err(ServerError::from)?;
+            Ok(ValidatedJson(json.into_inner()))
+        })
+    }
+}